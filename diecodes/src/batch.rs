@@ -0,0 +1,117 @@
+/*
+  An input can hold several codes, separated by ',' or ';', and a code with an "N#" prefix is
+  rolled N times:
+
+    6#3d6             six ability scores
+    d20+5, 2d6+3      an attack and its damage
+    4#d20+2; 4#d8     an attack and damage for each of four
+*/
+
+use crate::{Error, Result};
+use std::fmt::{Display, Formatter};
+
+// The most times one code can be rolled, so a typo can't keep fourad rolling for hours.
+const MAX_TIMES: u32 = 10_000;
+
+// One code from an input, and how many times to roll it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Batch<'a> {
+    pub code: &'a str,
+    pub times: u32,
+}
+
+impl Display for Batch<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.times == 1 {
+            write!(f, "{}", self.code)
+        } else {
+            write!(f, "{}#{}", self.times, self.code)
+        }
+    }
+}
+
+/*
+  GRAMMAR: batches    --> batch ( separator batch )*
+  GRAMMAR: batch      --> number '#' code
+  GRAMMAR:            --> code
+  GRAMMAR: separator  --> ','
+  GRAMMAR:            --> ';'
+*/
+// Empty codes, like the one after a trailing ',', are skipped. The codes themselves are not
+// parsed.
+pub fn batches(input: &str) -> Result<Vec<Batch<'_>>> {
+    input
+        .split([',', ';'])
+        .map(str::trim)
+        .filter(|code| !code.is_empty())
+        .map(|code| match code.split_once('#') {
+            Some((times, code)) => {
+                let times = times.trim();
+                let times: u32 = times.parse().map_err(|_| {
+                    Error::InvalidRepeat(format!("'{}' is not a number of times to roll", times))
+                })?;
+                if times == 0 {
+                    return Err(Error::InvalidRepeat(
+                        "cannot roll a code zero times".to_string(),
+                    ));
+                }
+                if times > MAX_TIMES {
+                    return Err(Error::InvalidRepeat(format!(
+                        "cannot roll a code more than {} times",
+                        MAX_TIMES
+                    )));
+                }
+                Ok(Batch {
+                    code: code.trim_start(),
+                    times,
+                })
+            }
+            None => Ok(Batch { code, times: 1 }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_batches() {
+        assert_eq!(
+            batches("2d6").unwrap(),
+            vec![Batch {
+                code: "2d6",
+                times: 1
+            }]
+        );
+        assert_eq!(
+            batches(" 6#3d6, d20+5 ;4 # d8,").unwrap(),
+            vec![
+                Batch {
+                    code: "3d6",
+                    times: 6
+                },
+                Batch {
+                    code: "d20+5",
+                    times: 1
+                },
+                Batch {
+                    code: "d8",
+                    times: 4
+                },
+            ]
+        );
+        assert_eq!(batches(" , ").unwrap(), vec![]);
+        assert_eq!(batches("6#3d6").unwrap()[0].to_string(), "6#3d6");
+
+        assert!(matches!(batches("0#d6"), Err(Error::InvalidRepeat(_))));
+        assert!(matches!(batches("x#d6"), Err(Error::InvalidRepeat(_))));
+        assert!(matches!(batches("#d6"), Err(Error::InvalidRepeat(_))));
+        assert_eq!(batches("10000#d6").unwrap()[0].times, 10000);
+        assert!(matches!(batches("10001#d6"), Err(Error::InvalidRepeat(_))));
+        assert!(matches!(
+            batches("4000000000#d6"),
+            Err(Error::InvalidRepeat(_))
+        ));
+    }
+}
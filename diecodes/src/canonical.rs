@@ -0,0 +1,124 @@
+use crate::grammar::{BinaryOp, Dice, DieCode, Directives, Expr, Selection, Sides};
+use std::convert::TryFrom;
+
+impl DieCode {
+    // An equivalent code in its simplest form, so that codes which roll the same way also display
+    // the same way: "1d6+0" becomes "d6", and "4d6kh4" becomes "4d6".
+    pub fn canonical(&self) -> DieCode {
+        DieCode {
+            expr: canonical_expr(&self.expr),
+            directives: Directives {
+                // Never exploding overrides exploding.
+                explode: self.directives.explode && !self.directives.never_explode,
+                never_explode: self.directives.never_explode,
+            },
+        }
+    }
+}
+
+fn canonical_expr(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Dice(dice) => Expr::Dice(canonical_dice(dice)),
+        Expr::Constant(value) => Expr::Constant(*value),
+        Expr::Negate(expr) => match canonical_expr(expr) {
+            Expr::Negate(expr) => *expr,
+            Expr::Constant(0) => Expr::Constant(0),
+            expr => Expr::Negate(Box::new(expr)),
+        },
+        Expr::Binary(op, lhs, rhs) => {
+            canonical_binary(*op, canonical_expr(lhs), canonical_expr(rhs))
+        }
+    }
+}
+
+fn canonical_binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    match (op, lhs, rhs) {
+        // Fold constants, as long as the result is still a constant.
+        (op, Expr::Constant(lhs), Expr::Constant(rhs)) => {
+            match op.apply(lhs as i64, rhs as i64).map(u32::try_from) {
+                Ok(Ok(value)) => Expr::Constant(value),
+                _ => Expr::binary(op, Expr::Constant(lhs), Expr::Constant(rhs)),
+            }
+        }
+        (BinaryOp::Add, expr, Expr::Constant(0))
+        | (BinaryOp::Add, Expr::Constant(0), expr)
+        | (BinaryOp::Subtract, expr, Expr::Constant(0))
+        | (BinaryOp::Multiply, expr, Expr::Constant(1))
+        | (BinaryOp::Multiply, Expr::Constant(1), expr)
+        | (BinaryOp::Divide(_), expr, Expr::Constant(1)) => expr,
+        (BinaryOp::Subtract, Expr::Constant(0), expr) => {
+            canonical_expr(&Expr::Negate(Box::new(expr)))
+        }
+        (BinaryOp::Add, lhs, Expr::Negate(rhs)) => {
+            Expr::Binary(BinaryOp::Subtract, Box::new(lhs), rhs)
+        }
+        (BinaryOp::Subtract, lhs, Expr::Negate(rhs)) => {
+            Expr::Binary(BinaryOp::Add, Box::new(lhs), rhs)
+        }
+        (op, lhs, rhs) => Expr::binary(op, lhs, rhs),
+    }
+}
+
+fn canonical_dice(dice: &Dice) -> Dice {
+    let mut dice = dice.clone();
+
+    let faces = match &dice.sides {
+        Sides::Die(sides) => vec![*sides],
+        Sides::Digits(digits) => digits.clone(),
+    };
+    // A reroll that can't match any face does nothing.
+    if let Some(reroll) = dice.reroll {
+        if !faces
+            .iter()
+            .any(|sides| (1..=*sides).any(|die| reroll.matches(die)))
+        {
+            dice.reroll = None;
+        }
+    }
+
+    // Exploding on the highest face is the default.
+    if let (Some(explode), Sides::Die(sides)) = (dice.explode.as_mut(), &dice.sides) {
+        if explode.threshold == Some(*sides) {
+            explode.threshold = None;
+        }
+    }
+
+    // Keeping every die is the same as keeping them all.
+    match dice.selection {
+        Selection::KeepHighest(count) | Selection::KeepLowest(count)
+            if count == dice.repeat.number =>
+        {
+            dice.selection = Selection::All
+        }
+        _ => {}
+    }
+
+    dice
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grammar::DieCode;
+
+    fn canonical(code: &str) -> String {
+        code.parse::<DieCode>().unwrap().canonical().to_string()
+    }
+
+    #[test]
+    fn test_canonical() {
+        assert_eq!(canonical("1d6+0"), "d6");
+        assert_eq!(canonical("0+2d6x1"), "2d6");
+        assert_eq!(canonical("d6/1"), "d6");
+        assert_eq!(canonical("2+3+d6"), "5+d6");
+        assert_eq!(canonical("d6+(2-3)"), "d6+(2-3)");
+        assert_eq!(canonical("d6+-2"), "d6-2");
+        assert_eq!(canonical("d6--2"), "d6+2");
+        assert_eq!(canonical("--d6"), "d6");
+        assert_eq!(canonical("0-d6"), "-d6");
+        assert_eq!(canonical("4d6kh4"), "4d6");
+        assert_eq!(canonical("d8E8+"), "d8E");
+        assert_eq!(canonical("d6r7"), "d6");
+        assert_eq!(canonical("d6!!"), "d6E");
+        assert_eq!(canonical("2d6 N"), "2d6 N");
+    }
+}
@@ -0,0 +1,199 @@
+use crate::grammar::parse_code;
+use crate::Error;
+use std::fmt::{Display, Formatter};
+use std::num::IntErrorKind;
+use std::ops::Range;
+
+// An error in parsing a DieCode, along with where it happened and maybe a way to fix it.
+//
+// The parsers only see what is left of the code, so they can't know where they are in it. They
+// record how much of the code was left, and parse_diecode() fills in the rest once it has the
+// whole code.
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub error: Error,
+    pub code: String,
+    // The bytes of `code` that are in error. An empty span points between two characters.
+    pub span: Range<usize>,
+    pub suggestion: Option<String>,
+    remaining: usize,
+}
+
+impl SyntaxError {
+    // The text shown under the carets.
+    pub fn label(&self) -> String {
+        match &self.error {
+            Error::UnexpectedChar(ch, _) => format!("expected '{}' here", ch),
+            Error::UnexpectedEndOfString(_) => "the code ends too soon".to_string(),
+            Error::ParseNumberError(_, err) => match err.kind() {
+                IntErrorKind::PosOverflow => "this number is too large".to_string(),
+                _ => "expected a number here".to_string(),
+            },
+            Error::ExpectedValue(_) => "expected a number or dice here".to_string(),
+            Error::ZeroOrOneSide => "dice need at least two sides".to_string(),
+            Error::ZeroRepeats => "cannot roll zero dice".to_string(),
+            Error::UnexpectedEOL(_) => "this is not part of the code".to_string(),
+            Error::InvalidDigitDie(msg)
+            | Error::InvalidReroll(msg)
+            | Error::InvalidExplosion(msg)
+            | Error::InvalidSelection(msg)
+            | Error::InvalidQuery(msg) => msg.clone(),
+            err => err.to_string(),
+        }
+    }
+
+    fn locate(mut self, code: &str) -> SyntaxError {
+        // Only the start of the code is trimmed before parsing, so the end lines up.
+        let end = code.trim_end().len();
+        let start = end - self.remaining;
+        self.span = start..start + self.span.len();
+        self.code = code.to_string();
+        self.suggestion = suggest(&self.code, &self.error);
+        self
+    }
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let column = self.code[..self.span.start].chars().count();
+        let width = self.code[self.span.clone()].chars().count().max(1);
+        writeln!(f, "{}", self.code)?;
+        write!(
+            f,
+            "{}{} {}",
+            " ".repeat(column),
+            "^".repeat(width),
+            self.label()
+        )?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n{}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+// Wraps `error`, which happened `len` bytes into `rest`.
+pub(crate) fn syntax_error(error: Error, rest: &str, len: usize) -> Error {
+    Error::Syntax(Box::new(SyntaxError {
+        error,
+        code: String::new(),
+        span: 0..len,
+        suggestion: None,
+        remaining: rest.len(),
+    }))
+}
+
+pub(crate) fn locate(error: Error, code: &str) -> Error {
+    match error {
+        Error::Syntax(err) => Error::Syntax(Box::new(err.locate(code))),
+        err => err,
+    }
+}
+
+fn suggest(code: &str, error: &Error) -> Option<String> {
+    if let Error::ParseNumberError(_, err) = error {
+        if *err.kind() == IntErrorKind::PosOverflow {
+            return Some(format!("numbers above {} are not supported", u32::MAX));
+        }
+    }
+
+    fixes(code, error)
+        .into_iter()
+        .find(|fixed| fixed != code && parse_code(fixed).is_ok())
+        .map(|fixed| format!("did you mean {}?", fixed))
+}
+
+// Common typos, and what they were probably meant to be.
+fn fixes(code: &str, error: &Error) -> Vec<String> {
+    let code = code.trim();
+    let mut fixes = vec![
+        // D66, 2D6X3
+        code.replace('D', "d").replace('X', "x"),
+    ];
+    // d 66, but not d6 E: a space before a directive is what keeps it off the last die.
+    let words: Vec<&str> = code.split_whitespace().collect();
+    if !words
+        .iter()
+        .skip(1)
+        .any(|word| word.starts_with(['E', 'N']))
+    {
+        fixes.push(words.concat());
+    }
+
+    // 2d
+    if code.ends_with('d') {
+        fixes.push(format!("{}6", code));
+    }
+    if let Error::UnexpectedEndOfString(_) = error {
+        // 2d6+, d6x
+        fixes.push(
+            code.trim_end_matches(['+', '-', 'x', '*', '/', '^', '~'])
+                .trim_end()
+                .to_string(),
+        );
+    }
+
+    let unclosed = code.matches('(').count() as isize - code.matches(')').count() as isize;
+    if unclosed > 0 {
+        fixes.push(format!("{}{}", code, ")".repeat(unclosed as usize)));
+    }
+    fixes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::grammar::parse_diecode;
+
+    fn syntax(code: &str) -> SyntaxError {
+        match parse_diecode(code).unwrap_err() {
+            Error::Syntax(err) => *err,
+            err => panic!("{:?} is not a SyntaxError", err),
+        }
+    }
+
+    #[test]
+    fn test_span() {
+        let err = syntax("2d6+x");
+        assert_eq!(err.span, 4..5);
+        assert_eq!(err.code, "2d6+x");
+
+        let err = syntax("  4d6kh5 ");
+        assert_eq!(err.span, 5..8);
+
+        let err = syntax("(d6+1");
+        assert_eq!(err.span, 5..5);
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            syntax("2d6+x").to_string(),
+            "2d6+x\n    ^ expected a number or dice here"
+        );
+        assert_eq!(
+            syntax("d6r").to_string(),
+            "d6r\n   ^ expected a number here"
+        );
+        assert_eq!(
+            syntax("4d6kh5").to_string(),
+            "4d6kh5\n   ^^^ cannot keep 5 of 4 dice"
+        );
+    }
+
+    #[test]
+    fn test_suggestions() {
+        assert_eq!(syntax("D66").suggestion.unwrap(), "did you mean d66?");
+        assert_eq!(syntax("d 66").suggestion.unwrap(), "did you mean d66?");
+        assert_eq!(syntax("2d").suggestion.unwrap(), "did you mean 2d6?");
+        assert_eq!(syntax("(d6+1").suggestion.unwrap(), "did you mean (d6+1)?");
+        assert_eq!(syntax("2d6+").suggestion.unwrap(), "did you mean 2d6?");
+        assert_eq!(
+            syntax("d5000000000").suggestion.unwrap(),
+            "numbers above 4294967295 are not supported"
+        );
+        assert_eq!(syntax("d6+q").suggestion, None);
+        // Joining these up would explode the d6 instead.
+        assert_eq!(syntax("d6 E E").suggestion, None);
+    }
+}
@@ -0,0 +1,736 @@
+use crate::executor::RollOptions;
+use crate::grammar::{
+    Dice, DieCode, Directives, Explode, ExplodeStyle, Expr, Reroll, Selection, Sides,
+};
+use crate::rollresult::digits_value;
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+
+// Exploding dice have no upper bound, so their chains are followed only until they become less
+// likely than this. The (tiny) probability of the remaining tail is dropped.
+const EXPLOSION_CUTOFF: f64 = 1e-12;
+
+// An explosion that only stops on a few faces can go on for thousands of rounds before reaching
+// EXPLOSION_CUTOFF, over more and more totals. Past these many rounds, or steps (one step per
+// die outcome added to a chain or state), Error::TooComplex is returned instead.
+const MAX_EXPLOSION_ROUNDS: u32 = 1000;
+const MAX_EXPLOSION_STEPS: usize = 2_000_000;
+
+// The most values a distribution may have, and the most pairs of values combining two may look
+// at. Dice with millions of sides, or sums over very many dice, give Error::TooComplex instead.
+const MAX_OUTCOMES: usize = 1_000_000;
+const MAX_COMBINE_STEPS: usize = 10_000_000;
+// Summing dice is a much cheaper step, so more of them are allowed.
+const MAX_SUM_STEPS: usize = 100_000_000;
+
+fn check_explosion(depth: u32, steps: usize) -> Result<()> {
+    if depth > MAX_EXPLOSION_ROUNDS || steps > MAX_EXPLOSION_STEPS {
+        Err(Error::TooComplex)
+    } else {
+        Ok(())
+    }
+}
+
+// The exact probability of every value a DieCode can roll.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    pmf: BTreeMap<i64, f64>,
+}
+
+impl Distribution {
+    pub fn constant(value: i64) -> Distribution {
+        Distribution {
+            pmf: std::iter::once((value, 1.0)).collect(),
+        }
+    }
+
+    pub fn uniform(sides: u32) -> Result<Distribution> {
+        if sides as usize > MAX_OUTCOMES {
+            return Err(Error::TooComplex);
+        }
+        let p = 1.0 / sides as f64;
+        Ok(Distribution {
+            pmf: (1..=sides as i64).map(|value| (value, p)).collect(),
+        })
+    }
+
+    pub fn probability(&self, value: i64) -> f64 {
+        self.pmf.get(&value).copied().unwrap_or(0.0)
+    }
+
+    // All of the possible values with their probabilities, in increasing order of value.
+    pub fn iter(&self) -> impl Iterator<Item = (i64, f64)> + '_ {
+        self.pmf.iter().map(|(value, p)| (*value, *p))
+    }
+
+    pub fn min(&self) -> Option<i64> {
+        self.pmf.keys().next().copied()
+    }
+
+    pub fn max(&self) -> Option<i64> {
+        self.pmf.keys().next_back().copied()
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.iter().map(|(value, p)| value as f64 * p).sum()
+    }
+
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.iter()
+            .map(|(value, p)| (value as f64 - mean).powi(2) * p)
+            .sum()
+    }
+
+    // The distribution of `op` applied to independent values drawn from `self` and `other`.
+    fn combine(&self, other: &Distribution, op: impl Fn(i64, i64) -> i64) -> Result<Distribution> {
+        self.try_combine(other, |lhs, rhs| Ok(op(lhs, rhs)))
+    }
+
+    fn try_combine(
+        &self,
+        other: &Distribution,
+        op: impl Fn(i64, i64) -> Result<i64>,
+    ) -> Result<Distribution> {
+        if self.pmf.len().saturating_mul(other.pmf.len()) > MAX_COMBINE_STEPS {
+            return Err(Error::TooComplex);
+        }
+        let mut pmf = BTreeMap::new();
+        for (lhs, lp) in self.iter() {
+            for (rhs, rp) in other.iter() {
+                *pmf.entry(op(lhs, rhs)?).or_insert(0.0) += lp * rp;
+            }
+        }
+        Ok(Distribution { pmf })
+    }
+
+    fn map(&self, op: impl Fn(i64) -> i64) -> Distribution {
+        let mut pmf = BTreeMap::new();
+        for (value, p) in self.iter() {
+            *pmf.entry(op(value)).or_insert(0.0) += p;
+        }
+        Distribution { pmf }
+    }
+}
+
+pub fn distribution(code: &DieCode, options: &RollOptions) -> Result<Distribution> {
+    Analyzer {
+        directives: &code.directives,
+        options,
+    }
+    .expr_distribution(&code.expr)
+}
+
+// The chances of each (value, score) a single die can end up with.
+type Outcomes = BTreeMap<(i64, i64), f64>;
+
+pub(crate) struct Analyzer<'a> {
+    pub(crate) directives: &'a Directives,
+    pub(crate) options: &'a RollOptions,
+}
+
+impl Analyzer<'_> {
+    // Every Dice in an Expr is rolled separately, so the operands of each operator are
+    // independent.
+    pub(crate) fn expr_distribution(&self, expr: &Expr) -> Result<Distribution> {
+        match expr {
+            Expr::Dice(dice) => self.dice_distribution(dice),
+            Expr::Constant(value) => Ok(Distribution::constant(*value as i64)),
+            Expr::Negate(expr) => Ok(self.expr_distribution(expr)?.map(|v| -v)),
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.expr_distribution(lhs)?;
+                let rhs = self.expr_distribution(rhs)?;
+                lhs.try_combine(&rhs, |lhs, rhs| op.apply(lhs, rhs))
+            }
+        }
+    }
+
+    pub(crate) fn dice_distribution(&self, dice: &Dice) -> Result<Distribution> {
+        let sides = self.options.sides(dice)?;
+        let explode = self.options.explosion(self.directives, dice, &sides)?;
+        // What a die adds to the subtotal: its value, or 1 if it hits the target of a pool.
+        let score = |value: i64, lowest: bool, highest: bool| match dice.target {
+            Some(target) => target.succeeds(value, lowest, highest) as i64,
+            None => value,
+        };
+
+        let (die, separate): (Outcomes, _) = match sides {
+            Sides::Digits(digits) => {
+                let lowest = digits_value(&vec![1; digits.len()]);
+                let highest = digits_value(&digits);
+                let die = digits_distribution(&digits, dice.reroll)?
+                    .iter()
+                    .map(|(value, p)| {
+                        let score = score(value, value == lowest, value == highest);
+                        ((value, score), p)
+                    })
+                    .collect();
+                (die, None)
+            }
+            Sides::Die(sides) => {
+                let face = face_distribution(sides, dice.reroll)?;
+                let max = sides as i64;
+                match explode {
+                    // Separate dice only differ from a compound chain when choosing among them
+                    // or counting them.
+                    Some(explode)
+                        if explode.style == ExplodeStyle::Separate
+                            && (dice.selection != Selection::All || dice.target.is_some()) =>
+                    {
+                        let die = face
+                            .iter()
+                            .map(|(value, p)| ((value, score(value, value == 1, value == max)), p))
+                            .collect();
+                        (die, Some((explode, sides)))
+                    }
+                    _ => {
+                        let mut die = BTreeMap::new();
+                        for ((first, total), p) in self.chain_outcomes(&face, sides, explode)? {
+                            let score = score(total, first == 1, first == max);
+                            *die.entry((total, score)).or_insert(0.0) += p;
+                        }
+                        (die, None)
+                    }
+                }
+            }
+        };
+
+        if dice.selection == Selection::All && separate.is_none() {
+            let mut pmf = BTreeMap::new();
+            for ((_, score), p) in die {
+                *pmf.entry(score).or_insert(0.0) += p;
+            }
+            sum_distribution(&Distribution { pmf }, dice.repeat.number)
+        } else {
+            self.selection_distribution(&die, separate, dice.repeat.number, dice.selection)
+        }
+    }
+
+    // A single die that may explode, with all of its explosions added in. The outcomes are keyed
+    // by the first roll and the total.
+    fn chain_outcomes(
+        &self,
+        face: &Distribution,
+        sides: u32,
+        explode: Option<Explode>,
+    ) -> Result<BTreeMap<(i64, i64), f64>> {
+        let explode = match explode {
+            Some(explode) => explode,
+            None => return Ok(face.iter().map(|(value, p)| ((value, value), p)).collect()),
+        };
+        let penalty = if explode.style == ExplodeStyle::Penetrate {
+            1
+        } else {
+            0
+        };
+
+        // `continuing` holds the chains that are still exploding.
+        let mut outcomes = BTreeMap::new();
+        let mut continuing: BTreeMap<(i64, i64), f64> = face
+            .iter()
+            .filter(|(value, _)| self.explodes(explode, *value, sides, 0))
+            .map(|(value, p)| ((value, value), p))
+            .collect();
+        for (value, p) in face.iter() {
+            if !self.explodes(explode, value, sides, 0) {
+                outcomes.insert((value, value), p);
+            }
+        }
+        let mut depth = 1u32;
+        let mut steps = 0;
+        while continuing.values().sum::<f64>() >= EXPLOSION_CUTOFF {
+            steps += continuing.len() * face.pmf.len();
+            check_explosion(depth, steps)?;
+            let mut next = BTreeMap::new();
+            for ((first, total), p) in &continuing {
+                for (value, fp) in face.iter() {
+                    let key = (*first, total + value - penalty);
+                    let target = if self.explodes(explode, value, sides, depth) {
+                        &mut next
+                    } else {
+                        &mut outcomes
+                    };
+                    *target.entry(key).or_insert(0.0) += p * fp;
+                }
+            }
+            continuing = next;
+            depth += 1;
+        }
+        Ok(outcomes)
+    }
+
+    fn explodes(&self, explode: Explode, value: i64, sides: u32, depth: u32) -> bool {
+        explode.explodes(value as u32, sides)
+            && self
+                .options
+                .max_explosions
+                .is_none_or(|max| depth < u32::from(max))
+    }
+
+    // The sum of the scores of the dice chosen by `selection` from `number` dice. Rather than
+    // enumerating every roll of every die, this tracks the distribution of the highest (or
+    // lowest) few dice seen so far, along with the sum of the rest. Keeping dice sums the tracked
+    // ones; dropping them sums the rest. If `separate` is set, each die can explode into more
+    // dice.
+    fn selection_distribution(
+        &self,
+        die: &Outcomes,
+        separate: Option<(Explode, u32)>,
+        number: u32,
+        selection: Selection,
+    ) -> Result<Distribution> {
+        let (count, highest, keep) = match selection {
+            Selection::All => (0, true, false),
+            Selection::KeepHighest(count) => (count, true, true),
+            Selection::KeepLowest(count) => (count, false, true),
+            Selection::DropHighest(count) => (count, true, false),
+            Selection::DropLowest(count) => (count, false, false),
+        };
+        let add = |(tracked, rest): &(Vec<(i64, i64)>, i64), outcome: (i64, i64)| {
+            let mut tracked = tracked.clone();
+            let mut rest = *rest;
+            tracked.insert(tracked.partition_point(|o| *o < outcome), outcome);
+            if tracked.len() > count as usize {
+                let (_, evicted) = if highest {
+                    tracked.remove(0)
+                } else {
+                    tracked.pop().unwrap()
+                };
+                // The sum of the untracked dice only matters when they're the ones kept.
+                if !keep {
+                    rest += evicted;
+                }
+            }
+            (tracked, rest)
+        };
+
+        let mut states: BTreeMap<(Vec<(i64, i64)>, i64), f64> =
+            std::iter::once(((vec![], 0), 1.0)).collect();
+        let mut steps = 0;
+        for _ in 0..number {
+            let mut done = BTreeMap::new();
+            let mut continuing = std::mem::take(&mut states);
+            let mut depth = 0u32;
+            while continuing.values().sum::<f64>() >= EXPLOSION_CUTOFF {
+                steps += continuing.len() * die.len();
+                check_explosion(depth, steps)?;
+                let mut next = BTreeMap::new();
+                for (state, p) in &continuing {
+                    for (outcome, dp) in die {
+                        let state = add(state, *outcome);
+                        let exploded = separate.is_some_and(|(explode, sides)| {
+                            self.explodes(explode, outcome.0, sides, depth)
+                        });
+                        let target = if exploded { &mut next } else { &mut done };
+                        *target.entry(state).or_insert(0.0) += p * dp;
+                    }
+                }
+                continuing = next;
+                depth += 1;
+            }
+            states = done;
+        }
+
+        let mut pmf = BTreeMap::new();
+        for ((tracked, rest), p) in states {
+            let value = if keep {
+                tracked.iter().map(|(_, score)| score).sum()
+            } else {
+                rest
+            };
+            *pmf.entry(value).or_insert(0.0) += p;
+        }
+        Ok(Distribution { pmf })
+    }
+}
+
+// The sum of `number` independent rolls of `die`. The values of a die are close together, so this
+// adds up one die at a time over arrays rather than maps.
+fn sum_distribution(die: &Distribution, number: u32) -> Result<Distribution> {
+    let (min, max) = match (die.min(), die.max()) {
+        (Some(min), Some(max)) => (min, max),
+        _ => return Ok(die.clone()),
+    };
+    let width = (max - min) as usize + 1;
+    let number = number as usize;
+    let total_width = (width - 1).saturating_mul(number).saturating_add(1);
+    let steps = total_width.saturating_mul(width).saturating_mul(number) / 2;
+    if total_width > MAX_OUTCOMES || steps > MAX_SUM_STEPS {
+        return Err(Error::TooComplex);
+    }
+
+    let mut face = vec![0.0; width];
+    for (value, p) in die.iter() {
+        face[(value - min) as usize] = p;
+    }
+    let mut sum = face.clone();
+    for _ in 1..number {
+        let mut next = vec![0.0; sum.len() + width - 1];
+        for (idx, p) in sum.iter().enumerate().filter(|(_, p)| **p > 0.0) {
+            for (offset, fp) in face.iter().enumerate() {
+                next[idx + offset] += p * fp;
+            }
+        }
+        sum = next;
+    }
+
+    let lowest = min * number as i64;
+    Ok(Distribution {
+        pmf: sum
+            .into_iter()
+            .enumerate()
+            .filter(|(_, p)| *p > 0.0)
+            .map(|(idx, p)| (lowest + idx as i64, p))
+            .collect(),
+    })
+}
+
+// Digit dice *never* explode. Digits are read the same way as rollresult::digits_value().
+fn digits_distribution(digits: &[u32], reroll: Option<Reroll>) -> Result<Distribution> {
+    Ok(digits
+        .iter()
+        .try_fold(Distribution::constant(0), |acc, sides| {
+            acc.combine(&face_distribution(*sides, reroll)?, |value, die| {
+                value * 10 + die % 10
+            })
+        })?
+        .map(|value| match value {
+            0 => 10i64.pow(digits.len() as u32),
+            value => value,
+        }))
+}
+
+// A single physical die, after any rerolls.
+pub(crate) fn face_distribution(sides: u32, reroll: Option<Reroll>) -> Result<Distribution> {
+    let uniform = Distribution::uniform(sides)?;
+    let reroll = match reroll {
+        Some(reroll) => reroll,
+        None => return Ok(uniform),
+    };
+
+    let matched: f64 = uniform
+        .iter()
+        .filter(|(face, _)| reroll.matches(*face as u32))
+        .map(|(_, p)| p)
+        .sum();
+    let pmf = uniform
+        .iter()
+        .map(|(face, p)| {
+            let kept = if reroll.matches(face as u32) { 0.0 } else { p };
+            if reroll.once {
+                // Either this face was rolled and kept, or it was the reroll.
+                (face, kept + matched * p)
+            } else {
+                // Rerolling until nothing matches picks evenly from the faces that don't.
+                (face, kept / (1.0 - matched))
+            }
+        })
+        .filter(|(_, p)| *p > 0.0)
+        .collect();
+    Ok(Distribution { pmf })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Error;
+
+    fn dist(code: &str) -> Distribution {
+        distribution(&code.parse().unwrap(), &RollOptions::default()).unwrap()
+    }
+
+    fn exploding(code: &str) -> Distribution {
+        let options = RollOptions {
+            explode: true,
+            ..RollOptions::default()
+        };
+        distribution(&code.parse().unwrap(), &options).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    fn assert_same(actual: Distribution, expected: Distribution) {
+        assert_eq!(actual.min(), expected.min());
+        assert_eq!(actual.max(), expected.max());
+        for (value, p) in expected.iter() {
+            assert_close(actual.probability(value), p);
+        }
+    }
+
+    #[test]
+    fn test_single_die() {
+        let d = dist("d6");
+        assert_eq!(d.min(), Some(1));
+        assert_eq!(d.max(), Some(6));
+        assert_close(d.probability(3), 1.0 / 6.0);
+        assert_close(d.probability(7), 0.0);
+        assert_close(d.mean(), 3.5);
+    }
+
+    #[test]
+    fn test_sum_and_modifier() {
+        let d = dist("2d6-2");
+        assert_eq!(d.min(), Some(0));
+        assert_eq!(d.max(), Some(10));
+        assert_close(d.probability(5), 6.0 / 36.0);
+        assert_close(d.probability(0), 1.0 / 36.0);
+    }
+
+    #[test]
+    fn test_product() {
+        let d = dist("d6xd6");
+        assert_eq!(d.min(), Some(1));
+        assert_eq!(d.max(), Some(36));
+        // 1x6, 6x1, 2x3, 3x2
+        assert_close(d.probability(6), 4.0 / 36.0);
+        assert_close(d.probability(7), 0.0);
+        assert_close(d.mean(), 12.25);
+    }
+
+    #[test]
+    fn test_d66() {
+        let d = dist("d66");
+        assert_eq!(d.iter().count(), 36);
+        assert_close(d.probability(34), 1.0 / 36.0);
+        assert_close(d.probability(17), 0.0);
+
+        let options = RollOptions {
+            no_digit_dice: true,
+            ..RollOptions::default()
+        };
+        let d = distribution(&"d66".parse().unwrap(), &options).unwrap();
+        assert_eq!(d.iter().count(), 66);
+        assert_close(d.probability(17), 1.0 / 66.0);
+    }
+
+    #[test]
+    fn test_expressions() {
+        let d = dist("2d6+d3+1");
+        assert_eq!(d.min(), Some(4));
+        assert_eq!(d.max(), Some(16));
+        assert_close(d.mean(), 10.0);
+
+        let d = dist("10-d6");
+        assert_eq!(d.min(), Some(4));
+        assert_eq!(d.max(), Some(9));
+
+        let d = dist("(d6+1)x2");
+        assert_close(d.probability(4), 1.0 / 6.0);
+        assert_close(d.probability(5), 0.0);
+
+        let d = dist("d6/2");
+        assert_close(d.probability(0), 1.0 / 6.0);
+        assert_close(d.probability(1), 2.0 / 6.0);
+        assert_close(d.probability(3), 1.0 / 6.0);
+
+        let d = dist("d6/^2");
+        assert_close(d.probability(0), 0.0);
+        assert_close(d.probability(3), 2.0 / 6.0);
+
+        assert!(matches!(
+            distribution(&"d6/(d2-1)".parse().unwrap(), &RollOptions::default()),
+            Err(Error::DivideByZero)
+        ));
+    }
+
+    #[test]
+    fn test_digit_dice() {
+        let d = dist("d666");
+        assert_eq!(d.iter().count(), 216);
+        assert_eq!(d.min(), Some(111));
+        assert_eq!(d.max(), Some(666));
+
+        let d = dist("d10d10");
+        assert_eq!(d.iter().count(), 100);
+        assert_eq!(d.min(), Some(1));
+        assert_eq!(d.max(), Some(100));
+        assert_close(d.probability(100), 0.01);
+        assert_close(d.mean(), 50.5);
+    }
+
+    #[test]
+    fn test_keep_and_drop() {
+        let d = dist("2d6kh1");
+        assert_close(d.probability(6), 11.0 / 36.0);
+        assert_close(d.probability(1), 1.0 / 36.0);
+
+        let d = dist("2d6kl1");
+        assert_close(d.probability(1), 11.0 / 36.0);
+
+        let d = dist("4d6kh3");
+        assert_eq!(d.min(), Some(3));
+        assert_eq!(d.max(), Some(18));
+        assert_close(d.probability(18), 21.0 / 1296.0);
+        assert_close(d.mean(), 15869.0 / 1296.0);
+
+        assert_same(dist("3d6dl1"), dist("3d6kh2"));
+        assert_same(dist("3d6dh1"), dist("3d6kl2"));
+    }
+
+    #[test]
+    fn test_reroll() {
+        let d = dist("d6r1");
+        assert_close(d.probability(1), 1.0 / 36.0);
+        assert_close(d.probability(4), 7.0 / 36.0);
+
+        let d = dist("d6R<3");
+        assert_eq!(d.min(), Some(3));
+        assert_close(d.probability(3), 0.25);
+        assert_close(d.mean(), 4.5);
+
+        let d = dist("d66R1");
+        assert_eq!(d.min(), Some(22));
+        assert_eq!(d.iter().count(), 25);
+    }
+
+    #[test]
+    fn test_exploding() {
+        let d = exploding("d6");
+        assert_close(d.probability(6), 0.0);
+        assert_close(d.probability(5), 1.0 / 6.0);
+        assert_close(d.probability(9), 1.0 / 36.0);
+        assert_close(d.mean(), 4.2);
+        assert_close(d.iter().map(|(_, p)| p).sum(), 1.0);
+
+        // The directive works without the flag.
+        assert_eq!(d, dist("d6E"));
+    }
+
+    #[test]
+    fn test_explosion_styles() {
+        // Compounding and separate explosions add up the same way.
+        assert_eq!(dist("d6!!"), exploding("d6"));
+        assert_eq!(dist("d6!"), exploding("d6"));
+
+        // Penetrating dice lose one for every explosion, so 6 is possible again.
+        let d = dist("d6!p");
+        assert_close(d.probability(6), 1.0 / 36.0);
+        assert_close(d.probability(10), 1.0 / 36.0);
+        assert_close(d.probability(11), 1.0 / 216.0);
+        assert_close(d.mean(), 4.0);
+
+        let d = dist("d8E7+");
+        assert_close(d.probability(7), 0.0);
+        assert_close(d.probability(9), 2.0 / 64.0);
+        assert_close(d.mean(), 4.5 * 4.0 / 3.0);
+    }
+
+    #[test]
+    fn test_separate_selection() {
+        // With separate explosions each explosion is another die to choose from.
+        let d = dist("d6!kl1");
+        assert_close(d.probability(1), 1.0 / 5.0);
+        assert_close(d.probability(6), 0.0);
+        assert_close(d.iter().map(|(_, p)| p).sum(), 1.0);
+
+        // A compounded die is only one die.
+        assert_eq!(dist("d6!!kl1"), dist("d6!!"));
+    }
+
+    #[test]
+    fn test_max_explosions() {
+        let options = RollOptions {
+            explode: true,
+            max_explosions: Some(1),
+            ..RollOptions::default()
+        };
+        let d = distribution(&"d6".parse().unwrap(), &options).unwrap();
+        assert_eq!(d.max(), Some(12));
+        assert_close(d.probability(12), 1.0 / 36.0);
+
+        let options = RollOptions {
+            max_explosions: Some(0),
+            ..options
+        };
+        assert_eq!(
+            distribution(&"d6!".parse().unwrap(), &options).unwrap(),
+            dist("d6")
+        );
+        assert_eq!(exploding("d6 N"), dist("d6"));
+    }
+
+    #[test]
+    fn test_pool() {
+        let d = dist("5d6>=5");
+        assert_eq!(d.min(), Some(0));
+        assert_eq!(d.max(), Some(5));
+        assert_close(d.probability(0), 32.0 / 243.0);
+        assert_close(d.mean(), 5.0 / 3.0);
+
+        assert_close(dist("d6<=3f").probability(1), 1.0 / 3.0);
+        assert_close(dist("d6>=7s").probability(1), 1.0 / 6.0);
+        assert_close(dist("d66<=20f").probability(1), 5.0 / 36.0);
+        assert_close(dist("4d6kh2>=5").probability(0), 16.0 / 81.0);
+    }
+
+    #[test]
+    fn test_pool_explosions() {
+        // Every 6 is a success that adds another die.
+        let d = dist("2d6!>=5");
+        assert_close(d.mean(), 0.8);
+        assert_close(d.iter().map(|(_, p)| p).sum(), 1.0);
+
+        // A compounded die is only one success, however far it explodes.
+        assert_close(dist("d6!!>=5").mean(), 1.0 / 3.0);
+        assert_close(dist("d6!!>=8s").mean(), 1.0 / 6.0);
+    }
+
+    #[test]
+    fn test_large_numbers() {
+        let d = dist("300d6");
+        assert_eq!(d.min(), Some(300));
+        assert_eq!(d.max(), Some(1800));
+        assert_close(d.mean(), 1050.0);
+
+        let d = dist("d1000+300");
+        assert_eq!(d.max(), Some(1300));
+        assert_close(d.probability(301), 0.001);
+
+        assert!(matches!(
+            distribution(
+                &"4294967295x4294967295x(d2+4294967295)".parse().unwrap(),
+                &RollOptions::default()
+            ),
+            Err(Error::Overflow)
+        ));
+    }
+
+    #[test]
+    fn test_too_complex() {
+        for code in &["d4000000000", "d1000000xd1000000", "5d100000", "200d10000"] {
+            assert!(
+                matches!(
+                    distribution(&code.parse().unwrap(), &RollOptions::default()),
+                    Err(Error::TooComplex)
+                ),
+                "{}",
+                code
+            );
+        }
+        assert!(Distribution::uniform(4294967295).is_err());
+        assert_eq!(Distribution::uniform(6).unwrap().max(), Some(6));
+    }
+
+    #[test]
+    fn test_long_explosions() {
+        // Exploding on 2 or more takes thousands of rounds to become unlikely enough to stop.
+        for code in &["d100E2+", "d100!2+kh1", "d100E2+>=50"] {
+            assert!(matches!(
+                distribution(&code.parse().unwrap(), &RollOptions::default()),
+                Err(Error::TooComplex)
+            ));
+        }
+
+        // More than 255 explosions is fine while there aren't too many outcomes.
+        let d = dist("d2E2+");
+        assert_close(d.iter().map(|(_, p)| p).sum(), 1.0);
+        assert!(d.max().unwrap() > 40);
+    }
+}
@@ -1,84 +1,326 @@
-use crate::grammar::{DieCode, Factor, Modifier};
-use crate::roller::RandRoller;
+use crate::grammar::{
+    Dice, DieCode, Directives, Explode, ExplodeStyle, Expr, Reroll, Selection, Sides, NEVER_STOPS,
+};
+use crate::observer::Observer;
 use crate::roller::Roller;
-use std::ops::ControlFlow;
-use tools::verbose;
+use crate::rollresult::{DiceResult, DieResult, RollNode, RollResult, Rolls};
+use crate::{Error, Result};
 
-pub fn execute(code: DieCode, explode: bool, force_66: bool) -> i16 {
-    execute_with_roller(code, explode, force_66, &mut RandRoller::default())
+// Settings from the caller that apply to every Dice in a DieCode.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct RollOptions {
+    // Explode every d6 that doesn't say how to explode (the same as the 'E' directive).
+    pub explode: bool,
+    // Read digit dice as ordinary dice, so d66 is a 66-sided die.
+    pub no_digit_dice: bool,
+    // The most times a single die may explode. None means there is no limit.
+    pub max_explosions: Option<u8>,
 }
 
-pub fn execute_with_roller<R>(code: DieCode, explode: bool, force_66: bool, roller: &mut R) -> i16
+impl RollOptions {
+    // How `dice` (with the given sides) explodes, if it does at all. Parsing rejects a spec
+    // that would never stop with the dice's reroll, but exploding every d6 can still add one.
+    pub(crate) fn explosion(
+        &self,
+        directives: &Directives,
+        dice: &Dice,
+        sides: &Sides,
+    ) -> Result<Option<Explode>> {
+        if directives.never_explode || self.max_explosions == Some(0) {
+            return Ok(None);
+        }
+        let explode = dice.explode.or_else(|| {
+            if (self.explode || directives.explode) && *sides == Sides::Die(6) {
+                Some(Explode::default())
+            } else {
+                None
+            }
+        });
+        match (explode, sides) {
+            (Some(explode), Sides::Die(sides)) if explode.never_stops(*sides, dice.reroll) => {
+                Err(Error::InvalidExplosion(NEVER_STOPS.to_string()))
+            }
+            _ => Ok(explode),
+        }
+    }
+
+    pub(crate) fn sides(&self, dice: &Dice) -> Result<Sides> {
+        if self.no_digit_dice {
+            dice.sides.without_digits()
+        } else {
+            Ok(dice.sides.clone())
+        }
+    }
+}
+
+pub fn execute_with_roller<R>(
+    code: DieCode,
+    options: &RollOptions,
+    roller: &mut R,
+) -> Result<RollResult>
+where
+    R: Roller,
+{
+    execute_observed(code, options, roller, &mut ())
+}
+
+// Rolls `code`, telling `observer` about each die and subtotal along the way.
+pub fn execute_observed<R, O>(
+    code: DieCode,
+    options: &RollOptions,
+    roller: &mut R,
+    observer: &mut O,
+) -> Result<RollResult>
 where
     R: Roller,
+    O: Observer,
 {
     Executor {
         code,
-        explode,
-        force_66,
+        options: *options,
+    }
+    .execute(roller, observer)
+}
+
+impl DieCode {
+    // Rolls a code that is already parsed, with the caller's own source of dice.
+    pub fn roll(&self, options: &RollOptions, roller: &mut impl Roller) -> Result<RollResult> {
+        execute_with_roller(self.clone(), options, roller)
     }
-    .execute(roller)
 }
 
 struct Executor {
     code: DieCode,
-    explode: bool,
-    force_66: bool,
+    options: RollOptions,
 }
 
 impl Executor {
-    fn execute(&self, roller: &mut impl Roller) -> i16 {
-        self.code
-            .factors
-            .iter()
-            .map(|f| self.execute_factor(f, roller))
-            .product()
+    fn execute(
+        &self,
+        roller: &mut impl Roller,
+        observer: &mut impl Observer,
+    ) -> Result<RollResult> {
+        let (node, total) = self.evaluate(&self.code.expr, roller, observer)?;
+        Ok(RollResult { node, total })
     }
 
-    fn execute_factor(&self, factor: &Factor, roller: &mut impl Roller) -> i16 {
-        let total = (0..factor.repeat.number)
-            .map(|_| self.roll(factor.sides, self.explode(), roller))
-            .sum();
+    fn evaluate(
+        &self,
+        expr: &Expr,
+        roller: &mut impl Roller,
+        observer: &mut impl Observer,
+    ) -> Result<(RollNode, i64)> {
+        let (node, value) = match expr {
+            Expr::Dice(dice) => {
+                let result = self.execute_dice(dice, roller, observer)?;
+                let subtotal = result.subtotal;
+                (RollNode::Dice(result), subtotal)
+            }
+            Expr::Constant(value) => {
+                observer.modifier(*value as i64);
+                return Ok((RollNode::Constant(*value as i64), *value as i64));
+            }
+            Expr::Negate(expr) => {
+                let (node, value) = self.evaluate(expr, roller, observer)?;
+                let value = value.checked_neg().ok_or(Error::Overflow)?;
+                (RollNode::Negate(Box::new(node)), value)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, lhs_value) = self.evaluate(lhs, roller, observer)?;
+                let (rhs, rhs_value) = self.evaluate(rhs, roller, observer)?;
+                let value = op.apply(lhs_value, rhs_value)?;
+                (RollNode::Binary(*op, Box::new(lhs), Box::new(rhs)), value)
+            }
+        };
+        observer.subtotal(expr, value);
+        Ok((node, value))
+    }
+
+    fn execute_dice(
+        &self,
+        dice: &Dice,
+        roller: &mut impl Roller,
+        observer: &mut impl Observer,
+    ) -> Result<DiceResult> {
+        let sides = self.options.sides(dice)?;
+        let explode = self
+            .options
+            .explosion(&self.code.directives, dice, &sides)?;
 
-        self.modify(&factor.modifier, total)
+        let mut rolled = vec![];
+        for _ in 0..dice.repeat.number {
+            self.roll(&sides, dice.reroll, explode, &mut rolled, roller, observer)?;
+        }
+        self.select(&dice.selection, &mut rolled);
+
+        let subtotal = match dice.target {
+            Some(target) => {
+                for die in rolled.iter_mut().filter(|die| !die.dropped) {
+                    let (lowest, highest) = naturals(die, &sides);
+                    die.succeeded = target.succeeds(die.value(), lowest, highest);
+                }
+                rolled.iter().filter(|die| die.succeeded).count() as i64
+            }
+            None => rolled
+                .iter()
+                .filter(|die| !die.dropped)
+                .try_fold(0i64, |sum, die| sum.checked_add(die.value()))
+                .ok_or(Error::Overflow)?,
+        };
+
+        Ok(DiceResult {
+            dice: rolled,
+            subtotal,
+        })
     }
 
-    fn roll(&self, sides: u8, explode: bool, roller: &mut impl Roller) -> i16 {
-        if sides == 66 && !self.force_66 {
-            // special case!
-            return self.roll_d66(roller);
+    // Roll one die, adding it to `rolled`. A die that explodes into separate dice adds those too.
+    fn roll(
+        &self,
+        sides: &Sides,
+        reroll: Option<Reroll>,
+        explode: Option<Explode>,
+        rolled: &mut Vec<DieResult>,
+        roller: &mut impl Roller,
+        observer: &mut impl Observer,
+    ) -> Result<()> {
+        match sides {
+            Sides::Die(sides) => self.roll_die(*sides, reroll, explode, rolled, roller, observer),
+            Sides::Digits(digits) => {
+                rolled.push(self.roll_digits(digits, reroll, roller, observer)?);
+                Ok(())
+            }
         }
+    }
 
-        match std::iter::repeat_with(|| roller.roll(sides))
-            .inspect(|die| verbose!("Rolled: {}", die))
-            .try_fold(0, |sum, die| {
-                if sides != 6 || die != 6 || !explode {
-                    ControlFlow::Break(sum + die)
-                } else {
-                    ControlFlow::Continue(sum + die)
+    fn roll_die(
+        &self,
+        sides: u32,
+        reroll: Option<Reroll>,
+        explode: Option<Explode>,
+        rolled: &mut Vec<DieResult>,
+        roller: &mut impl Roller,
+        observer: &mut impl Observer,
+    ) -> Result<()> {
+        let mut rerolled = vec![];
+        let mut rolls = vec![];
+        // Wider than max_explosions, since with no limit a die can explode more than 255 times.
+        let mut explosions = 0u32;
+        loop {
+            let die = self.roll_once(sides, false, reroll, &mut rerolled, roller, observer)?;
+            let exploded = match explode {
+                Some(explode) => {
+                    explode.explodes(die, sides)
+                        && self
+                            .options
+                            .max_explosions
+                            .is_none_or(|max| explosions < u32::from(max))
                 }
-            }) {
-            ControlFlow::Break(sum) => sum as i16,
-            _ => panic!("This shouldn't happen"),
+                None => false,
+            };
+
+            if explode.map(|explode| explode.style) == Some(ExplodeStyle::Separate) {
+                rolled.push(DieResult {
+                    rerolled: std::mem::take(&mut rerolled),
+                    exploded,
+                    ..DieResult::chain(vec![die])
+                });
+            } else {
+                rolls.push(die);
+            }
+
+            if !exploded {
+                break;
+            }
+            explosions += 1;
+            observer.exploded(sides, die);
         }
+
+        if rolls.is_empty() {
+            return Ok(());
+        }
+        let rolls = if explode.map(|explode| explode.style) == Some(ExplodeStyle::Penetrate) {
+            DieResult::penetrating(rolls)
+        } else {
+            DieResult::chain(rolls)
+        };
+        rolled.push(DieResult { rerolled, ..rolls });
+        Ok(())
     }
 
-    fn roll_d66(&self, roller: &mut impl Roller) -> i16 {
-        // d66 *never* explodes.
-        self.roll(6, false, roller) * 10 + self.roll(6, false, roller)
+    fn roll_digits(
+        &self,
+        digits: &[u32],
+        reroll: Option<Reroll>,
+        roller: &mut impl Roller,
+        observer: &mut impl Observer,
+    ) -> Result<DieResult> {
+        // Digit dice *never* explode.
+        let mut rerolled = vec![];
+        let rolls = digits
+            .iter()
+            .map(|sides| self.roll_once(*sides, true, reroll, &mut rerolled, roller, observer))
+            .collect::<Result<_>>()?;
+        Ok(DieResult {
+            rerolled,
+            ..DieResult::digits(rolls)
+        })
     }
 
-    fn modify(&self, modifier: &Modifier, unmodified: i16) -> i16 {
-        unmodified
-            + match modifier {
-                Modifier::None => 0i16,
-                Modifier::Plus(operand) => *operand as i16,
-                Modifier::Minus(operand) => -(*operand as i16),
+    // Roll one physical die, rerolling it as many times as `reroll` allows. The values that were
+    // rerolled away are added to `rerolled`. A `digit` is one die of a digit die.
+    fn roll_once(
+        &self,
+        sides: u32,
+        digit: bool,
+        reroll: Option<Reroll>,
+        rerolled: &mut Vec<u32>,
+        roller: &mut impl Roller,
+        observer: &mut impl Observer,
+    ) -> Result<u32> {
+        let mut die = roller.try_roll(sides)?;
+        if digit {
+            observer.digit(sides, die);
+        } else {
+            observer.rolled(sides, die);
+        }
+        if let Some(reroll) = reroll {
+            while reroll.matches(die) {
+                rerolled.push(die);
+                die = roller.try_roll(sides)?;
+                observer.rerolled(sides, die);
+                if reroll.once {
+                    break;
+                }
             }
+        }
+        Ok(die)
     }
 
-    fn explode(&self) -> bool {
-        self.explode || self.code.directives.explode
+    fn select(&self, selection: &Selection, dice: &mut [DieResult]) {
+        let (keep, highest) = selection.kept(dice.len() as u32);
+
+        let mut order: Vec<usize> = (0..dice.len()).collect();
+        order.sort_by_key(|idx| dice[*idx].value());
+        if highest {
+            order.reverse();
+        }
+        for idx in order.into_iter().skip(keep as usize) {
+            dice[idx].dropped = true;
+        }
+    }
+}
+
+// Whether `die` first rolled its lowest and highest faces.
+fn naturals(die: &DieResult, sides: &Sides) -> (bool, bool) {
+    match (&die.rolls, sides) {
+        (Rolls::Digits(rolls), Sides::Digits(digits)) => (
+            rolls.iter().all(|roll| *roll == 1),
+            rolls.iter().zip(digits).all(|(roll, sides)| roll == sides),
+        ),
+        (Rolls::Chain(rolls), Sides::Die(sides))
+        | (Rolls::Penetrating(rolls), Sides::Die(sides)) => (rolls[0] == 1, rolls[0] == *sides),
+        _ => (false, false),
     }
 }
 
@@ -87,11 +329,21 @@ mod test {
     use super::*;
     use crate::roller::iterroller::IterRoller;
 
+    fn options(explode: bool, no_digit_dice: bool) -> RollOptions {
+        RollOptions {
+            explode,
+            no_digit_dice,
+            ..RollOptions::default()
+        }
+    }
+
     #[test]
     fn test_basic() {
         let mut roller = IterRoller::new(vec![3, 4, 5, 1, 1, 1, 1, 1].into_iter());
         assert_eq!(
-            execute_with_roller("d6".parse().unwrap(), true, false, &mut roller),
+            execute_with_roller("d6".parse().unwrap(), &options(true, false), &mut roller)
+                .unwrap()
+                .total,
             3
         );
     }
@@ -100,7 +352,9 @@ mod test {
     fn test_basic_exploding() {
         let mut roller = IterRoller::new(vec![6, 6, 3, 4, 5, 1, 1, 1, 1, 1].into_iter());
         assert_eq!(
-            execute_with_roller("d6".parse().unwrap(), true, false, &mut roller),
+            execute_with_roller("d6".parse().unwrap(), &options(true, false), &mut roller)
+                .unwrap()
+                .total,
             15
         );
     }
@@ -109,7 +363,9 @@ mod test {
     fn test_two_exploding() {
         let mut roller = IterRoller::new(vec![6, 6, 3, 6, 5, 1, 1, 1, 1, 1].into_iter());
         assert_eq!(
-            execute_with_roller("2d6".parse().unwrap(), true, false, &mut roller),
+            execute_with_roller("2d6".parse().unwrap(), &options(true, false), &mut roller)
+                .unwrap()
+                .total,
             26
         );
     }
@@ -118,7 +374,9 @@ mod test {
     fn test_plusmod() {
         let mut roller = IterRoller::new(vec![3, 4, 5, 1, 1, 1, 1, 1].into_iter());
         assert_eq!(
-            execute_with_roller("d6+4".parse().unwrap(), true, false, &mut roller),
+            execute_with_roller("d6+4".parse().unwrap(), &options(true, false), &mut roller)
+                .unwrap()
+                .total,
             7
         );
     }
@@ -127,7 +385,9 @@ mod test {
     fn test_minusmod() {
         let mut roller = IterRoller::new(vec![3, 4, 5, 1, 1, 1, 1, 1].into_iter());
         assert_eq!(
-            execute_with_roller("d6-2".parse().unwrap(), true, false, &mut roller),
+            execute_with_roller("d6-2".parse().unwrap(), &options(true, false), &mut roller)
+                .unwrap()
+                .total,
             1
         );
     }
@@ -136,7 +396,9 @@ mod test {
     fn test_negative_result() {
         let mut roller = IterRoller::new(vec![3, 4, 5, 1, 1, 1, 1, 1].into_iter());
         assert_eq!(
-            execute_with_roller("d6-7".parse().unwrap(), true, false, &mut roller),
+            execute_with_roller("d6-7".parse().unwrap(), &options(true, false), &mut roller)
+                .unwrap()
+                .total,
             -4
         );
     }
@@ -145,7 +407,9 @@ mod test {
     fn test_d66() {
         let mut roller = IterRoller::new(vec![3, 4, 5, 1, 1, 1, 1, 1].into_iter());
         assert_eq!(
-            execute_with_roller("d66".parse().unwrap(), false, false, &mut roller),
+            execute_with_roller("d66".parse().unwrap(), &options(false, false), &mut roller)
+                .unwrap()
+                .total,
             34
         );
     }
@@ -154,7 +418,13 @@ mod test {
     fn test_d6xd6() {
         let mut roller = IterRoller::new(vec![3, 6, 4, 1, 1, 1, 1, 1].into_iter());
         assert_eq!(
-            execute_with_roller("d6xd6".parse().unwrap(), false, false, &mut roller),
+            execute_with_roller(
+                "d6xd6".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            )
+            .unwrap()
+            .total,
             18
         );
     }
@@ -163,7 +433,9 @@ mod test {
     fn test_only_explode_d6() {
         let mut roller = IterRoller::new(vec![7, 6, 4, 1, 1, 1, 1, 1].into_iter());
         assert_eq!(
-            execute_with_roller("2d7".parse().unwrap(), true, false, &mut roller),
+            execute_with_roller("2d7".parse().unwrap(), &options(true, false), &mut roller)
+                .unwrap()
+                .total,
             13
         )
     }
@@ -172,14 +444,615 @@ mod test {
     fn test_force_d66() {
         let mut roller = IterRoller::new(vec![5, 6, 4, 1, 1, 1, 1, 1].into_iter());
         assert_eq!(
-            execute_with_roller("d66".parse().unwrap(), true, false, &mut roller),
+            execute_with_roller("d66".parse().unwrap(), &options(true, false), &mut roller)
+                .unwrap()
+                .total,
             56
         );
 
         let mut roller = IterRoller::new(vec![5, 6, 4, 1, 1, 1, 1, 1].into_iter());
         assert_eq!(
-            execute_with_roller("d66".parse().unwrap(), true, true, &mut roller),
+            execute_with_roller("d66".parse().unwrap(), &options(true, true), &mut roller)
+                .unwrap()
+                .total,
             5
         );
     }
+
+    #[test]
+    fn test_breakdown() {
+        let mut roller = IterRoller::new(vec![4, 6, 6, 2, 3, 5].into_iter());
+        let result = execute_with_roller(
+            "(2d6+1)xd66".parse().unwrap(),
+            &options(true, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(
+            result.dice(),
+            vec![
+                &DiceResult {
+                    dice: vec![DieResult::chain(vec![4]), DieResult::chain(vec![6, 6, 2])],
+                    subtotal: 18,
+                },
+                &DiceResult {
+                    dice: vec![DieResult::digits(vec![3, 5])],
+                    subtotal: 35,
+                },
+            ]
+        );
+        assert_eq!(result.total, 665);
+        assert_eq!(result.to_string(), "([4, 6→6→2] + 1) x [3|5] = 665");
+    }
+
+    #[test]
+    fn test_expressions() {
+        let mut roller = IterRoller::new(vec![3, 4, 2, 5, 1, 6, 6].into_iter());
+        assert_eq!(
+            execute_with_roller(
+                "2d6+d3+1".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            )
+            .unwrap()
+            .total,
+            10
+        );
+        assert_eq!(
+            execute_with_roller(
+                "10-d6".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            )
+            .unwrap()
+            .total,
+            5
+        );
+        assert_eq!(
+            execute_with_roller(
+                "(d6+1)x2".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            )
+            .unwrap()
+            .total,
+            4
+        );
+        assert_eq!(
+            execute_with_roller(
+                "-d6/4".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            )
+            .unwrap()
+            .total,
+            -2
+        );
+        assert_eq!(
+            execute_with_roller(
+                "d6/^4".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            )
+            .unwrap()
+            .total,
+            2
+        );
+    }
+
+    #[test]
+    fn test_divide_by_zero() {
+        let mut roller = IterRoller::new(vec![1, 1].into_iter());
+        assert!(matches!(
+            execute_with_roller(
+                "d6/(d2-1)".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            ),
+            Err(crate::Error::DivideByZero)
+        ));
+    }
+
+    #[test]
+    fn test_digit_dice() {
+        let mut roller = IterRoller::new(vec![3, 4, 5, 1, 8, 2].into_iter());
+        assert_eq!(
+            execute_with_roller("d666".parse().unwrap(), &options(false, false), &mut roller)
+                .unwrap()
+                .total,
+            345
+        );
+        assert_eq!(
+            execute_with_roller("d88".parse().unwrap(), &options(false, false), &mut roller)
+                .unwrap()
+                .total,
+            18
+        );
+
+        let mut roller = IterRoller::new(vec![88].into_iter());
+        assert_eq!(
+            execute_with_roller("d88".parse().unwrap(), &options(false, true), &mut roller)
+                .unwrap()
+                .total,
+            88
+        );
+
+        let mut roller = IterRoller::new(vec![600].into_iter());
+        assert_eq!(
+            execute_with_roller("d666".parse().unwrap(), &options(false, true), &mut roller)
+                .unwrap()
+                .total,
+            600
+        );
+    }
+
+    #[test]
+    fn test_percentile() {
+        let mut roller = IterRoller::new(vec![10, 7, 4, 10, 10, 10].into_iter());
+        assert_eq!(
+            execute_with_roller(
+                "d10d10".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            )
+            .unwrap()
+            .total,
+            7
+        );
+        assert_eq!(
+            execute_with_roller(
+                "d10d10".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            )
+            .unwrap()
+            .total,
+            40
+        );
+        // Percentile chains ignore the opt-out, and "00" is 100.
+        assert_eq!(
+            execute_with_roller(
+                "d10d10".parse().unwrap(),
+                &options(false, true),
+                &mut roller
+            )
+            .unwrap()
+            .total,
+            100
+        );
+    }
+
+    #[test]
+    fn test_keep_and_drop() {
+        let mut roller = IterRoller::new(vec![3, 1, 6, 4].into_iter());
+        let result = execute_with_roller(
+            "4d6kh3".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 13);
+        assert!(result.dice()[0].dice[1].dropped);
+        assert_eq!(
+            result.dice()[0]
+                .dice
+                .iter()
+                .filter(|die| die.dropped)
+                .count(),
+            1
+        );
+
+        let mut roller = IterRoller::new(vec![5, 2].into_iter());
+        assert_eq!(
+            execute_with_roller(
+                "2d6kl1+1".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            )
+            .unwrap()
+            .total,
+            3
+        );
+
+        let mut roller = IterRoller::new(vec![5, 2, 4].into_iter());
+        assert_eq!(
+            execute_with_roller(
+                "3d6dl1".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            )
+            .unwrap()
+            .total,
+            9
+        );
+
+        let mut roller = IterRoller::new(vec![5, 2, 4].into_iter());
+        assert_eq!(
+            execute_with_roller(
+                "3d6dh2".parse().unwrap(),
+                &options(false, false),
+                &mut roller
+            )
+            .unwrap()
+            .total,
+            2
+        );
+    }
+
+    #[test]
+    fn test_reroll_once() {
+        let mut roller = IterRoller::new(vec![1, 1, 4, 5].into_iter());
+        let result = execute_with_roller(
+            "2d6r1".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        // The second 1 stays, because the reroll only happens once.
+        assert_eq!(result.total, 5);
+        assert_eq!(result.dice()[0].dice[0].rerolled, vec![1]);
+        assert_eq!(result.dice()[0].dice[1].rerolled, vec![] as Vec<u32>);
+    }
+
+    #[test]
+    fn test_reroll_until() {
+        let mut roller = IterRoller::new(vec![1, 2, 1, 5, 4].into_iter());
+        let result = execute_with_roller(
+            "d6R<3".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 5);
+        assert_eq!(result.dice()[0].dice[0].rerolled, vec![1, 2, 1]);
+    }
+
+    #[test]
+    fn test_reroll_digits() {
+        let mut roller = IterRoller::new(vec![1, 3, 2].into_iter());
+        let result = execute_with_roller(
+            "d66r1".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 32);
+        assert_eq!(result.dice()[0].dice[0].rerolled, vec![1]);
+    }
+
+    #[test]
+    fn test_explosion_styles() {
+        let mut roller = IterRoller::new(vec![6, 6, 2, 3].into_iter());
+        let result = execute_with_roller(
+            "2d6!!".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 17);
+        assert_eq!(result.dice()[0].dice.len(), 2);
+
+        let mut roller = IterRoller::new(vec![6, 6, 2, 3].into_iter());
+        let result =
+            execute_with_roller("2d6!".parse().unwrap(), &options(false, false), &mut roller)
+                .unwrap();
+        assert_eq!(result.total, 17);
+        assert_eq!(result.dice()[0].dice.len(), 4);
+
+        let mut roller = IterRoller::new(vec![6, 6, 2, 3].into_iter());
+        let result = execute_with_roller(
+            "2d6!p".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 15);
+    }
+
+    #[test]
+    fn test_explosion_threshold() {
+        let mut roller = IterRoller::new(vec![7, 8, 3].into_iter());
+        let result = execute_with_roller(
+            "d8E7+".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 18);
+
+        // Any die can explode with its own spec, even without -X.
+        let mut roller = IterRoller::new(vec![10, 4].into_iter());
+        let result =
+            execute_with_roller("d10E".parse().unwrap(), &options(false, false), &mut roller)
+                .unwrap();
+        assert_eq!(result.total, 14);
+    }
+
+    #[test]
+    fn test_max_explosions() {
+        let options = RollOptions {
+            explode: true,
+            max_explosions: Some(2),
+            ..RollOptions::default()
+        };
+        let mut roller = IterRoller::new(vec![6, 6, 6, 6, 1].into_iter());
+        let result = execute_with_roller("d6".parse().unwrap(), &options, &mut roller).unwrap();
+        assert_eq!(result.total, 18);
+    }
+
+    #[test]
+    fn test_many_explosions() {
+        // 300 twos explode a d100E2+, and then a 1 stops it.
+        let mut roller = IterRoller::new(std::iter::repeat_n(2, 300).chain(std::iter::once(1)));
+        let result = execute_with_roller(
+            "d100E2+".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 601);
+
+        let options = RollOptions {
+            max_explosions: Some(255),
+            ..RollOptions::default()
+        };
+        let mut roller = IterRoller::new(std::iter::repeat(2));
+        let result =
+            execute_with_roller("d100E2+".parse().unwrap(), &options, &mut roller).unwrap();
+        assert_eq!(result.total, 512);
+    }
+
+    #[test]
+    fn test_explosion_never_stops() {
+        // Rerolling everything below a 6 leaves only 6s, so exploding them would never stop,
+        // whether the explosion comes from the directive or from -X.
+        for (code, explode) in [("d6R<6 E", false), ("d6R<6", true), ("2d6R<=5+d6 E", false)] {
+            let mut roller = IterRoller::new(std::iter::repeat(6));
+            let err =
+                execute_with_roller(code.parse().unwrap(), &options(explode, false), &mut roller)
+                    .unwrap_err();
+            assert!(matches!(err, Error::InvalidExplosion(_)), "{}", code);
+        }
+
+        // Rerolling once, or not exploding at all, is fine.
+        let mut roller = IterRoller::new(vec![1, 2].into_iter());
+        let result = execute_with_roller(
+            "d6r<6 E".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 2);
+        let mut roller = IterRoller::new(vec![6].into_iter());
+        let result = execute_with_roller(
+            "d6R<6 N".parse().unwrap(),
+            &options(true, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 6);
+    }
+
+    #[test]
+    fn test_never_explode() {
+        let mut roller = IterRoller::new(vec![6, 6, 1].into_iter());
+        let result = execute_with_roller(
+            "d6!+d6E N".parse().unwrap(),
+            &options(true, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 12);
+    }
+
+    #[test]
+    fn test_pool() {
+        let mut roller = IterRoller::new(vec![5, 2, 6, 4, 1].into_iter());
+        let result = execute_with_roller(
+            "5d6>=5".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 2);
+        assert_eq!(result.to_string(), "[5*, 2, 6*, 4, 1] = 2");
+
+        // Pools are numbers like any other.
+        let mut roller = IterRoller::new(vec![5, 2, 6].into_iter());
+        let result = execute_with_roller(
+            "3d6>4+1".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 3);
+
+        let mut roller = IterRoller::new(vec![6, 3].into_iter());
+        let result = execute_with_roller(
+            "2d6<=3".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 1);
+    }
+
+    #[test]
+    fn test_pool_rules() {
+        let mut roller = IterRoller::new(vec![1, 6, 4].into_iter());
+        let result = execute_with_roller(
+            "3d6<=4fs".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 2);
+        assert!(!result.dice()[0].dice[0].succeeded);
+        assert!(result.dice()[0].dice[1].succeeded);
+
+        // An explosion doesn't change what the die first rolled.
+        let mut roller = IterRoller::new(vec![6, 1, 3].into_iter());
+        let result = execute_with_roller(
+            "2d6>=8s".parse().unwrap(),
+            &options(true, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 1);
+    }
+
+    #[test]
+    fn test_pool_explosions() {
+        // Each separate explosion is another die in the pool.
+        let mut roller = IterRoller::new(vec![6, 6, 2, 5].into_iter());
+        let result = execute_with_roller(
+            "2d6!>=5".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 3);
+        assert_eq!(result.to_string(), "[6!*, 6!*, 2, 5*] = 3");
+
+        // A compounded die is only one.
+        let mut roller = IterRoller::new(vec![6, 6, 2, 5].into_iter());
+        let result = execute_with_roller(
+            "2d6!!>=5".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 2);
+    }
+
+    #[test]
+    fn test_pool_selection() {
+        let mut roller = IterRoller::new(vec![5, 6, 2, 4].into_iter());
+        let result = execute_with_roller(
+            "4d6kl2>=4".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 1);
+    }
+
+    #[test]
+    fn test_large_numbers() {
+        let mut roller = IterRoller::new(std::iter::repeat(6));
+        let result = execute_with_roller(
+            "300d6".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 1800);
+
+        let mut roller = IterRoller::new(vec![999].into_iter());
+        let result = execute_with_roller(
+            "d1000+300".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 1299);
+
+        let mut roller = IterRoller::new(vec![66, 66, 66].into_iter());
+        let result = execute_with_roller(
+            "d66xd66xd66".parse().unwrap(),
+            &options(false, true),
+            &mut roller,
+        )
+        .unwrap();
+        assert_eq!(result.total, 287496);
+    }
+
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl Observer for Events {
+        fn rolled(&mut self, sides: u32, die: u32) {
+            self.0.push(format!("d{}={}", sides, die));
+        }
+
+        fn digit(&mut self, sides: u32, die: u32) {
+            self.0.push(format!("digit d{}={}", sides, die));
+        }
+
+        fn rerolled(&mut self, sides: u32, die: u32) {
+            self.0.push(format!("reroll d{}={}", sides, die));
+        }
+
+        fn exploded(&mut self, sides: u32, die: u32) {
+            self.0.push(format!("explode d{}={}", sides, die));
+        }
+
+        fn modifier(&mut self, value: i64) {
+            self.0.push(format!("modifier {}", value));
+        }
+
+        fn subtotal(&mut self, expr: &Expr, value: i64) {
+            self.0.push(format!("{} = {}", expr, value));
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let mut roller = IterRoller::new(vec![6, 2, 1, 3, 4].into_iter());
+        let mut events = Events::default();
+        let result = execute_observed(
+            "(d6!+2)xd66r1".parse().unwrap(),
+            &options(false, false),
+            &mut roller,
+            &mut events,
+        )
+        .unwrap();
+        assert_eq!(result.total, 340);
+        assert_eq!(
+            events.0,
+            vec![
+                "d6=6",
+                "explode d6=6",
+                "d6=2",
+                "d6! = 8",
+                "modifier 2",
+                "d6!+2 = 10",
+                "digit d6=1",
+                "reroll d6=3",
+                "digit d6=4",
+                "d66r1 = 34",
+                "(d6!+2)xd66r1 = 340",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overflow() {
+        let roll = |code: &str| {
+            let mut roller = IterRoller::new(vec![].into_iter());
+            execute_with_roller(code.parse().unwrap(), &options(false, false), &mut roller)
+        };
+
+        // Just inside and just outside of an i64.
+        assert_eq!(
+            roll("4294967295x2147483647").unwrap().total,
+            9223372030412324865
+        );
+        assert!(matches!(
+            roll("4294967295x2147483649"),
+            Err(Error::Overflow)
+        ));
+        assert!(matches!(
+            roll("-4294967295x2147483647-4294967295x2147483647"),
+            Err(Error::Overflow)
+        ));
+        assert!(matches!(
+            roll("4294967295x2147483647+4294967295x2147483647"),
+            Err(Error::Overflow)
+        ));
+        assert!(matches!(
+            roll("(4294967295x2147483647)x2/~1"),
+            Err(Error::Overflow)
+        ));
+    }
 }
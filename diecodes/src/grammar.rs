@@ -1,19 +1,29 @@
 /*
    2d6+3  (all dice types)
    d6xd10
-   d66, d88 (deal with ambiguity)
+   2d6+d3+1, (d6+1)x2, 10-d6
+   d6/2, d6/^2, d6/~2 (round down, up, or to nearest)
+   d66, d88, d666, d10d10 (digit dice)
 
    d6E  (explode!)
+   2d6E, d8E7+, d6!, d6!!, d6!p (explode these dice: compound, separate, penetrating)
+   d6xd6 N (never explode)
+   4d6kh3, 2d6kl1, 3d6dl1 (keep/drop)
+   d6r1, d6R<3 (reroll once, reroll until)
+   5d6>=5, 3d6>=4fs (pools: count the dice that hit the target)
 
    // Arbitrary string of d6xd6xd6xd6
 */
 
+use crate::diagnostic::{locate, syntax_error};
 use crate::{Error, Result};
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct DieCode {
-    pub factors: Vec<Factor>,
+    pub expr: Expr,
     pub directives: Directives,
 }
 
@@ -25,27 +35,253 @@ impl FromStr for DieCode {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
-pub struct Factor {
+// Displays the code so that it parses back to the same DieCode.
+impl Display for DieCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.expr, self.directives)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expr {
+    Dice(Dice),
+    Constant(u32),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    // Every Dice in the expression, from left to right.
+    pub fn dice(&self) -> Vec<&Dice> {
+        match self {
+            Expr::Dice(dice) => vec![dice],
+            Expr::Constant(_) => vec![],
+            Expr::Negate(expr) => expr.dice(),
+            Expr::Binary(_, lhs, rhs) => {
+                let mut dice = lhs.dice();
+                dice.extend(rhs.dice());
+                dice
+            }
+        }
+    }
+
+    // Higher binds more tightly. Used to put back only the parentheses that are needed.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(op, _, _) => op.precedence(),
+            Expr::Negate(_) => 3,
+            Expr::Dice(_) | Expr::Constant(_) => 4,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut Formatter<'_>, parens: bool) -> std::fmt::Result {
+        if parens {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Dice(dice) => write!(f, "{}", dice),
+            Expr::Constant(value) => write!(f, "{}", value),
+            Expr::Negate(expr) => {
+                write!(f, "-")?;
+                expr.fmt_operand(f, expr.precedence() < 3)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                lhs.fmt_operand(f, lhs.precedence() < op.precedence())?;
+                write!(f, "{}", op)?;
+                rhs.fmt_operand(f, rhs.precedence() <= op.precedence())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide(Rounding),
+}
+
+impl BinaryOp {
+    pub fn apply(&self, lhs: i64, rhs: i64) -> Result<i64> {
+        match self {
+            BinaryOp::Add => lhs.checked_add(rhs).ok_or(Error::Overflow),
+            BinaryOp::Subtract => lhs.checked_sub(rhs).ok_or(Error::Overflow),
+            BinaryOp::Multiply => lhs.checked_mul(rhs).ok_or(Error::Overflow),
+            BinaryOp::Divide(rounding) => rounding.divide(lhs, rhs),
+        }
+    }
+
+    // Higher binds more tightly.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Subtract => 1,
+            BinaryOp::Multiply | BinaryOp::Divide(_) => 2,
+        }
+    }
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BinaryOp::Add => write!(f, "+"),
+            BinaryOp::Subtract => write!(f, "-"),
+            BinaryOp::Multiply => write!(f, "x"),
+            BinaryOp::Divide(Rounding::Down) => write!(f, "/"),
+            BinaryOp::Divide(Rounding::Up) => write!(f, "/^"),
+            BinaryOp::Divide(Rounding::Nearest) => write!(f, "/~"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Rounding {
+    Down,
+    Up,
+    // Halves round up.
+    Nearest,
+}
+
+impl Rounding {
+    pub fn divide(&self, lhs: i64, rhs: i64) -> Result<i64> {
+        if rhs == 0 {
+            return Err(Error::DivideByZero);
+        }
+        // Nothing in between can overflow an i128.
+        let (lhs, rhs) = (lhs as i128, rhs as i128);
+        let quotient = match self {
+            Rounding::Down => floor_div(lhs, rhs),
+            Rounding::Up => -floor_div(-lhs, rhs),
+            // lhs/rhs + 1/2, rounded down.
+            Rounding::Nearest => floor_div(2 * lhs + rhs, 2 * rhs),
+        };
+        i64::try_from(quotient).map_err(|_| Error::Overflow)
+    }
+}
+
+fn floor_div(lhs: i128, rhs: i128) -> i128 {
+    let quotient = lhs / rhs;
+    if lhs % rhs != 0 && (lhs < 0) != (rhs < 0) {
+        quotient - 1
+    } else {
+        quotient
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dice {
     pub repeat: Repeat,
-    pub sides: u8,
-    pub modifier: Modifier,
+    pub sides: Sides,
+    pub reroll: Option<Reroll>,
+    pub explode: Option<Explode>,
+    pub selection: Selection,
+    // When set, the Dice count the dice that hit the target instead of adding them up.
+    pub target: Option<Target>,
 }
 
-impl Default for Factor {
-    // The default Factor is "d6"
+impl Display for Dice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}d{}", self.repeat, self.sides)?;
+        if let Some(reroll) = self.reroll {
+            write!(f, "{}", reroll)?;
+        }
+        if let Some(explode) = self.explode {
+            write!(f, "{}", explode)?;
+        }
+        write!(f, "{}", self.selection)?;
+        if let Some(target) = self.target {
+            write!(f, "{}", target)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Dice {
+    // The default Dice is "d6"
     fn default() -> Self {
-        Factor {
+        Dice {
             repeat: Default::default(),
-            sides: 6,
-            modifier: Default::default(),
+            sides: Sides::Die(6),
+            reroll: None,
+            explode: None,
+            selection: Default::default(),
+            target: None,
         }
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Sides {
+    Die(u32),
+    // A "digit die" like d66 or d10d10: one die is rolled for each digit of the result.
+    Digits(Vec<u32>),
+}
+
+impl Sides {
+    // How these sides read when digit dice are turned off. "d66" becomes a 66-sided die, but a
+    // chain like "d10d10" can't be read any other way, so it is left alone.
+    pub fn without_digits(&self) -> Result<Sides> {
+        match self {
+            Sides::Digits(digits) if digits.iter().all(|digit| *digit < 10) => {
+                let number = digits.iter().fold(0, |acc, digit| acc * 10 + digit);
+                Ok(Sides::Die(number))
+            }
+            _ => Ok(self.clone()),
+        }
+    }
+}
+
+impl Display for Sides {
+    // A Die(n) whose digits are all 2-9 (like 66) will read back as digit dice.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sides::Die(sides) => write!(f, "{}", sides),
+            Sides::Digits(digits) if digits.iter().all(|digit| (2..=9).contains(digit)) => {
+                digits.iter().try_for_each(|digit| write!(f, "{}", digit))
+            }
+            Sides::Digits(digits) => {
+                for (idx, digit) in digits.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, "d")?;
+                    }
+                    write!(f, "{}", digit)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Repeat {
-    pub number: u8,
+    pub number: u32,
+}
+
+impl Display for Repeat {
+    // A single die is written without its repeat: "d6", not "1d6".
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.number != 1 {
+            write!(f, "{}", self.number)?;
+        }
+        Ok(())
+    }
 }
 
 impl Default for Repeat {
@@ -54,90 +290,396 @@ impl Default for Repeat {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
-pub enum Modifier {
-    None,
-    Plus(u8),
-    Minus(u8),
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reroll {
+    pub compare: Compare,
+    pub target: u32,
+    // Reroll a matching die only once, rather than until it stops matching.
+    pub once: bool,
 }
 
-impl Default for Modifier {
-    fn default() -> Self {
-        Modifier::None
+impl Reroll {
+    pub fn matches(&self, die: u32) -> bool {
+        self.compare.matches(die as i64, self.target as i64)
+    }
+}
+
+impl Display for Reroll {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", if self.once { 'r' } else { 'R' })?;
+        // '=' is the default, so it's left out.
+        if self.compare != Compare::Equal {
+            write!(f, "{}", self.compare)?;
+        }
+        write!(f, "{}", self.target)
     }
 }
 
-#[derive(Debug, Default, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Target {
+    pub compare: Compare,
+    pub number: u32,
+    // A natural 1 always fails.
+    pub ones_fail: bool,
+    // A natural roll of the highest face always succeeds.
+    pub max_succeeds: bool,
+}
+
+impl Target {
+    // `lowest` and `highest` are set when the die rolled its lowest or highest face (before any
+    // explosions). For digit dice, every digit has to show it.
+    pub fn succeeds(&self, value: i64, lowest: bool, highest: bool) -> bool {
+        if self.ones_fail && lowest {
+            false
+        } else if self.max_succeeds && highest {
+            true
+        } else {
+            self.compare.matches(value, self.number as i64)
+        }
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.compare, self.number)?;
+        if self.ones_fail {
+            write!(f, "f")?;
+        }
+        if self.max_succeeds {
+            write!(f, "s")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compare {
+    Equal,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Compare {
+    pub fn matches(&self, value: i64, target: i64) -> bool {
+        match self {
+            Compare::Equal => value == target,
+            Compare::Less => value < target,
+            Compare::LessOrEqual => value <= target,
+            Compare::Greater => value > target,
+            Compare::GreaterOrEqual => value >= target,
+        }
+    }
+}
+
+impl Display for Compare {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compare::Equal => write!(f, "="),
+            Compare::Less => write!(f, "<"),
+            Compare::LessOrEqual => write!(f, "<="),
+            Compare::Greater => write!(f, ">"),
+            Compare::GreaterOrEqual => write!(f, ">="),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Explode {
+    pub style: ExplodeStyle,
+    // Dice showing this or more explode. If missing, only the highest side explodes.
+    pub threshold: Option<u32>,
+}
+
+impl Explode {
+    pub fn explodes(&self, die: u32, sides: u32) -> bool {
+        die >= self.threshold.unwrap_or(sides)
+    }
+
+    // Whether rerolling until a die stops matching only leaves faces that explode, so the die
+    // would never stop.
+    pub fn never_stops(&self, sides: u32, reroll: Option<Reroll>) -> bool {
+        reroll.filter(|reroll| !reroll.once).is_some_and(|reroll| {
+            (1..=sides).all(|die| reroll.matches(die) || self.explodes(die, sides))
+        })
+    }
+}
+
+impl Display for Explode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.style {
+            ExplodeStyle::Compound => write!(f, "E")?,
+            ExplodeStyle::Separate => write!(f, "!")?,
+            ExplodeStyle::Penetrate => write!(f, "!p")?,
+        }
+        if let Some(threshold) = self.threshold {
+            write!(f, "{}+", threshold)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExplodeStyle {
+    // Each explosion is added to the die that exploded (the 4AD way). 'E' or "!!".
+    #[default]
+    Compound,
+    // Each explosion rolls a new die. '!'
+    Separate,
+    // Like Compound, but every roll after the first counts one less. "!p"
+    Penetrate,
+}
+
+// Which of a Dice's dice count towards its total.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Selection {
+    #[default]
+    All,
+    KeepHighest(u32),
+    KeepLowest(u32),
+    DropHighest(u32),
+    DropLowest(u32),
+}
+
+impl Selection {
+    // How many of `number` dice are kept, and whether they are the highest (or lowest) ones.
+    pub fn kept(&self, number: u32) -> (u32, bool) {
+        match *self {
+            Selection::All => (number, true),
+            Selection::KeepHighest(count) => (count, true),
+            Selection::KeepLowest(count) => (count, false),
+            Selection::DropHighest(count) => (number - count, false),
+            Selection::DropLowest(count) => (number - count, true),
+        }
+    }
+}
+
+impl Display for Selection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selection::All => Ok(()),
+            Selection::KeepHighest(count) => write!(f, "kh{}", count),
+            Selection::KeepLowest(count) => write!(f, "kl{}", count),
+            Selection::DropHighest(count) => write!(f, "dh{}", count),
+            Selection::DropLowest(count) => write!(f, "dl{}", count),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Directives {
     pub explode: bool,
+    // Nothing explodes, whatever the code or the caller asks for.
+    pub never_explode: bool,
 }
 
-/*
-  Arbitrary string of d6xd6xd6xd6
+impl Display for Directives {
+    // Directives are set off by a space, so they aren't read as part of the last Dice.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.never_explode {
+            write!(f, " N")
+        } else if self.explode {
+            write!(f, " E")
+        } else {
+            Ok(())
+        }
+    }
+}
 
-  GRAMMAR: diecode    --> factor codetail directives
+/*
+  GRAMMAR: diecode    --> expr directives
 */
 pub fn parse_diecode(s: &str) -> Result<DieCode> {
-    let s = s.trim();
-
-    let mut factors = vec![];
+    parse_code(s).map_err(|err| locate(err, s))
+}
 
-    let (factor, rest) = parse_factor(s)?;
-    factors.push(factor);
+// parse_diecode(), without placing errors in the code.
+pub(crate) fn parse_code(s: &str) -> Result<DieCode> {
+    let s = s.trim();
 
-    let rest = parse_codetail(rest, &mut factors)?;
+    let (expr, rest) = parse_expr(s)?;
 
-    let (directives, rest) = parse_directives(rest)?;
+    let (directives, rest) = parse_directives(rest.trim_start())?;
 
     if !rest.is_empty() {
-        return Err(Error::UnexpectedEOL(rest.to_string()));
+        return Err(syntax_error(
+            Error::UnexpectedEOL(rest.to_string()),
+            rest,
+            rest.len(),
+        ));
     }
 
-    Ok(DieCode {
-        factors,
-        directives,
-    })
+    Ok(DieCode { expr, directives })
 }
 
 /*
-  GRAMMAR: codetail   --> 'x' factor codetail
+  GRAMMAR: expr       --> term exprtail
+  GRAMMAR: exprtail   --> '+' term exprtail
+  GRAMMAR:            --> '-' term exprtail
   GRAMMAR:            -->
 */
-fn parse_codetail<'a>(s: &'a str, factors: &mut Vec<Factor>) -> Result<&'a str> {
-    if let Some(ch) = s.chars().next() {
-        if ch == 'x' {
-            let (factor, rest) = parse_factor(&s[1..])?;
-            factors.push(factor);
+pub(crate) fn parse_expr(s: &str) -> Result<(Expr, &str)> {
+    let (mut expr, mut rest) = parse_term(s)?;
+    loop {
+        let trimmed = rest.trim_start();
+        let (op, after_op) = if let Some(after_op) = trimmed.strip_prefix('+') {
+            (BinaryOp::Add, after_op)
+        } else if let Some(after_op) = trimmed.strip_prefix('-') {
+            (BinaryOp::Subtract, after_op)
+        } else {
+            return Ok((expr, rest));
+        };
 
-            return parse_codetail(rest, factors);
-        }
+        let (rhs, after_rhs) = parse_term(after_op)?;
+        expr = Expr::binary(op, expr, rhs);
+        rest = after_rhs;
+    }
+}
+
+/*
+  GRAMMAR: term       --> unary termtail
+  GRAMMAR: termtail   --> mulop unary termtail
+  GRAMMAR:            -->
+*/
+fn parse_term(s: &str) -> Result<(Expr, &str)> {
+    let (mut expr, mut rest) = parse_unary(s)?;
+    while let Some((op, after_op)) = parse_mulop(rest.trim_start()) {
+        let (rhs, after_rhs) = parse_unary(after_op)?;
+        expr = Expr::binary(op, expr, rhs);
+        rest = after_rhs;
+    }
+    Ok((expr, rest))
+}
+
+/*
+  GRAMMAR: mulop      --> 'x'
+  GRAMMAR:            --> '*'
+  GRAMMAR:            --> "/^"
+  GRAMMAR:            --> "/~"
+  GRAMMAR:            --> '/'
+*/
+fn parse_mulop(s: &str) -> Option<(BinaryOp, &str)> {
+    if let Some(rest) = s.strip_prefix('x').or_else(|| s.strip_prefix('*')) {
+        Some((BinaryOp::Multiply, rest))
+    } else if let Some(rest) = s.strip_prefix("/^") {
+        Some((BinaryOp::Divide(Rounding::Up), rest))
+    } else if let Some(rest) = s.strip_prefix("/~") {
+        Some((BinaryOp::Divide(Rounding::Nearest), rest))
+    } else {
+        s.strip_prefix('/')
+            .map(|rest| (BinaryOp::Divide(Rounding::Down), rest))
+    }
+}
+
+/*
+  GRAMMAR: unary      --> '-' unary
+  GRAMMAR:            --> atom
+*/
+fn parse_unary(s: &str) -> Result<(Expr, &str)> {
+    let s = s.trim_start();
+    if let Some(rest) = s.strip_prefix('-') {
+        let (expr, rest) = parse_unary(rest)?;
+        Ok((Expr::Negate(Box::new(expr)), rest))
+    } else {
+        parse_atom(s)
     }
-    Ok(s)
 }
 
 /*
-  GRAMMAR: factor     --> repeat 'd' sides modifier
+  GRAMMAR: atom       --> '(' expr ')'
+  GRAMMAR:            --> dice
+  GRAMMAR:            --> number
 */
-fn parse_factor(s: &str) -> Result<(Factor, &str)> {
+fn parse_atom(s: &str) -> Result<(Expr, &str)> {
+    if let Some(rest) = s.strip_prefix('(') {
+        let (expr, rest) = parse_expr(rest)?;
+        let rest = rest.trim_start();
+        return match rest.strip_prefix(')') {
+            Some(rest) => Ok((expr, rest)),
+            None if rest.is_empty() => Err(syntax_error(
+                Error::UnexpectedEndOfString(s.to_string()),
+                rest,
+                0,
+            )),
+            None => Err(syntax_error(
+                Error::UnexpectedChar(')', rest.to_string()),
+                rest,
+                char_len(rest),
+            )),
+        };
+    }
+
+    if s.is_empty() {
+        return Err(syntax_error(
+            Error::UnexpectedEndOfString(s.to_string()),
+            s,
+            0,
+        ));
+    }
+    if !s.starts_with(|ch: char| ch.is_ascii_digit() || ch == 'd') {
+        return Err(syntax_error(
+            Error::ExpectedValue(s.to_string()),
+            s,
+            char_len(s),
+        ));
+    }
+
+    // A number is a constant unless it's the repeat for some dice.
+    let end = s.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(s.len());
+    if end > 0 && !s[end..].starts_with('d') {
+        let (number, rest) = parse_number(s)?;
+        return Ok((Expr::Constant(number), rest));
+    }
+
+    let (dice, rest) = parse_dice(s)?;
+    Ok((Expr::Dice(dice), rest))
+}
+
+/*
+  GRAMMAR: dice       --> repeat 'd' sides reroll explode selection target
+*/
+fn parse_dice(s: &str) -> Result<(Dice, &str)> {
     let (repeat, rest) = parse_repeat(s)?;
 
     if let Some(ch) = rest.chars().next() {
         if ch != 'd' {
-            return Err(Error::UnexpectedChar('d', rest.to_string()));
+            return Err(syntax_error(
+                Error::UnexpectedChar('d', rest.to_string()),
+                rest,
+                ch.len_utf8(),
+            ));
         }
     } else {
-        return Err(Error::UnexpectedEndOfString(s.to_string()));
+        return Err(syntax_error(
+            Error::UnexpectedEndOfString(s.to_string()),
+            rest,
+            0,
+        ));
     }
 
     let (sides, rest) = parse_sides(&rest[1..])?;
-    let (modifier, rest) = parse_modifier(rest)?;
+    let (reroll, rest) = parse_reroll(rest, &sides)?;
+    let (explode, rest) = parse_explode(rest, &sides, reroll)?;
+    let (selection, rest) = parse_selection(rest, &repeat)?;
+    let (target, rest) = parse_target(rest)?;
 
-    let factor = Factor {
+    let dice = Dice {
         repeat,
         sides,
-        modifier,
+        reroll,
+        explode,
+        selection,
+        target,
     };
-    Ok((factor, rest))
+    Ok((dice, rest))
 }
 
 /*
@@ -151,7 +693,7 @@ fn parse_repeat(s: &str) -> Result<(Repeat, &str)> {
     } else {
         parse_number(s).and_then(|(number, rest)| {
             if number == 0 {
-                Err(Error::ZeroRepeats)
+                Err(syntax_error(Error::ZeroRepeats, s, s.len() - rest.len()))
             } else {
                 Ok((Repeat { number }, rest))
             }
@@ -159,12 +701,66 @@ fn parse_repeat(s: &str) -> Result<(Repeat, &str)> {
     }
 }
 
-fn parse_sides(s: &str) -> Result<(u8, &str)> {
+/*
+  GRAMMAR: sides      --> digits sidestail
+  GRAMMAR: sidestail  --> 'd' digits sidestail
+  GRAMMAR:            -->
+*/
+fn parse_sides(s: &str) -> Result<(Sides, &str)> {
+    let (mut digits, mut rest) = parse_digits(s)?;
+    while let Some(chained) = rest
+        .strip_prefix('d')
+        .filter(|chained| chained.starts_with(|ch: char| ch.is_ascii_digit()))
+    {
+        let (more, chained_rest) = parse_digits(chained)?;
+        digits.extend(more);
+        rest = chained_rest;
+    }
+
+    if digits.len() == 1 {
+        return Ok((Sides::Die(digits[0]), rest));
+    }
+    if digits.len() > MAX_DIGITS {
+        return Err(syntax_error(
+            Error::InvalidDigitDie(format!("at most {} digits are allowed", MAX_DIGITS)),
+            s,
+            s.len() - rest.len(),
+        ));
+    }
+    if digits.iter().any(|digit| *digit > 10) {
+        return Err(syntax_error(
+            Error::InvalidDigitDie(
+                "each digit must be rolled with a die of 10 or fewer sides".to_string(),
+            ),
+            s,
+            s.len() - rest.len(),
+        ));
+    }
+    Ok((Sides::Digits(digits), rest))
+}
+
+// Enough digits for d6666, without overflowing the result.
+const MAX_DIGITS: usize = 4;
+
+/*
+  A run of two or more digits from 2-9 names one die per digit (d66, d88, d666).
+  Anything else is the number of sides on a single die.
+
+  GRAMMAR: digits     --> [2-9][2-9]+
+  GRAMMAR:            --> number
+*/
+fn parse_digits(s: &str) -> Result<(Vec<u32>, &str)> {
+    let end = s.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(s.len());
+    let run = &s[..end];
+    if run.len() > 1 && run.chars().all(|ch| ('2'..='9').contains(&ch)) {
+        return Ok((run.bytes().map(|b| (b - b'0') as u32).collect(), &s[end..]));
+    }
+
     parse_number(s).and_then(|(sides, rest)| {
         if sides == 0 || sides == 1 {
-            Err(Error::ZeroOrOneSide)
+            Err(syntax_error(Error::ZeroOrOneSide, s, s.len() - rest.len()))
         } else {
-            Ok((sides, rest))
+            Ok((vec![sides], rest))
         }
     })
 }
@@ -172,67 +768,293 @@ fn parse_sides(s: &str) -> Result<(u8, &str)> {
 /*
   GRAMMAR: number     --> [[:digit:]]+
 */
-fn parse_number(s: &str) -> Result<(u8, &str)> {
-    if let Some(end) = s.find(|ch: char| !ch.is_ascii_digit()) {
-        let number: u8 = s[..end]
-            .parse()
-            .map_err(|err| Error::ParseNumberError(s[..end].to_string(), err))?;
-        Ok((number, &s[end..]))
+fn parse_number(s: &str) -> Result<(u32, &str)> {
+    let end = s.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(s.len());
+    let number: u32 = s[..end]
+        .parse()
+        .map_err(|err| syntax_error(Error::ParseNumberError(s[..end].to_string(), err), s, end))?;
+    Ok((number, &s[end..]))
+}
+
+/*
+  GRAMMAR: reroll     --> 'r' compare number
+  GRAMMAR:            --> 'R' compare number
+  GRAMMAR:            -->
+*/
+fn parse_reroll<'a>(s: &'a str, sides: &Sides) -> Result<(Option<Reroll>, &'a str)> {
+    let (once, rest) = if let Some(rest) = s.strip_prefix('r') {
+        (true, rest)
+    } else if let Some(rest) = s.strip_prefix('R') {
+        (false, rest)
     } else {
-        let number: u8 = s
-            .parse()
-            .map_err(|err| Error::ParseNumberError(s.to_string(), err))?;
-        Ok((number, ""))
+        return Ok((None, s));
+    };
+
+    let (compare, rest) = parse_compare(rest);
+    let (target, rest) = parse_number(rest)?;
+    let reroll = Reroll {
+        compare,
+        target,
+        once,
+    };
+
+    // Rerolling until a die stops matching would never end if every face matches.
+    let dice = match sides {
+        Sides::Die(sides) => std::slice::from_ref(sides),
+        Sides::Digits(digits) => digits.as_slice(),
+    };
+    if !once
+        && dice
+            .iter()
+            .any(|sides| (1..=*sides).all(|die| reroll.matches(die)))
+    {
+        return Err(syntax_error(
+            Error::InvalidReroll("every side would be rerolled forever".to_string()),
+            s,
+            s.len() - rest.len(),
+        ));
+    }
+
+    Ok((Some(reroll), rest))
+}
+
+/*
+  GRAMMAR: compare    --> "<="
+  GRAMMAR:            --> ">="
+  GRAMMAR:            --> '<'
+  GRAMMAR:            --> '>'
+  GRAMMAR:            --> '='
+  GRAMMAR:            -->
+*/
+pub(crate) fn parse_compare(s: &str) -> (Compare, &str) {
+    if let Some(rest) = s.strip_prefix("<=") {
+        (Compare::LessOrEqual, rest)
+    } else if let Some(rest) = s.strip_prefix(">=") {
+        (Compare::GreaterOrEqual, rest)
+    } else if let Some(rest) = s.strip_prefix('<') {
+        (Compare::Less, rest)
+    } else if let Some(rest) = s.strip_prefix('>') {
+        (Compare::Greater, rest)
+    } else if let Some(rest) = s.strip_prefix('=') {
+        (Compare::Equal, rest)
+    } else {
+        // A bare number is the same as '='.
+        (Compare::Equal, s)
     }
 }
 
 /*
-  GRAMMAR: modifier   --> '+' operand
-  GRAMMAR:            --> '-' operand
+  GRAMMAR: explode    --> 'E' threshold
+  GRAMMAR:            --> "!!" threshold
+  GRAMMAR:            --> "!p" threshold
+  GRAMMAR:            --> '!' threshold
+  GRAMMAR:            -->
+  GRAMMAR: threshold  --> number '+'
   GRAMMAR:            -->
 */
-fn parse_modifier(s: &str) -> Result<(Modifier, &str)> {
-    if let Some(rest) = s.strip_prefix('+') {
-        let (operand, rest) = parse_operand(rest)?;
-        Ok((Modifier::Plus(operand), rest))
-    } else if let Some(rest) = s.strip_prefix('-') {
-        let (operand, rest) = parse_operand(rest)?;
-        Ok((Modifier::Minus(operand), rest))
+fn parse_explode<'a>(
+    s: &'a str,
+    sides: &Sides,
+    reroll: Option<Reroll>,
+) -> Result<(Option<Explode>, &'a str)> {
+    let (style, rest) = if let Some(rest) = s.strip_prefix('E') {
+        (ExplodeStyle::Compound, rest)
+    } else if let Some(rest) = s.strip_prefix("!!") {
+        (ExplodeStyle::Compound, rest)
+    } else if let Some(rest) = s.strip_prefix("!p") {
+        (ExplodeStyle::Penetrate, rest)
+    } else if let Some(rest) = s.strip_prefix('!') {
+        (ExplodeStyle::Separate, rest)
+    } else {
+        return Ok((None, s));
+    };
+
+    let sides = match sides {
+        Sides::Die(sides) => *sides,
+        Sides::Digits(_) => {
+            return Err(syntax_error(
+                Error::InvalidExplosion("digit dice cannot explode".to_string()),
+                s,
+                s.len() - rest.len(),
+            ))
+        }
+    };
+
+    let (threshold, after) = if rest.starts_with(|ch: char| ch.is_ascii_digit()) {
+        let (threshold, after) = parse_number(rest)?;
+        let after = match after.strip_prefix('+') {
+            Some(after) => after,
+            None => {
+                return Err(syntax_error(
+                    Error::UnexpectedChar('+', after.to_string()),
+                    after,
+                    char_len(after),
+                ))
+            }
+        };
+        // Exploding on every side would never end.
+        if threshold < 2 || threshold > sides {
+            return Err(syntax_error(
+                Error::InvalidExplosion(format!("a d{} cannot explode on {}+", sides, threshold)),
+                rest,
+                rest.len() - after.len(),
+            ));
+        }
+        (Some(threshold), after)
     } else {
-        Ok((Modifier::None, s))
+        (None, rest)
+    };
+    let explode = Explode { style, threshold };
+
+    // Nor would it if rerolling only leaves faces that explode.
+    if explode.never_stops(sides, reroll) {
+        return Err(syntax_error(
+            Error::InvalidExplosion(NEVER_STOPS.to_string()),
+            s,
+            s.len() - after.len(),
+        ));
     }
+
+    Ok((Some(explode), after))
 }
 
+pub(crate) const NEVER_STOPS: &str = "every side left after rerolling would explode";
+
 /*
-  GRAMMAR: operand    --> number
+  GRAMMAR: selection  --> "kh" number
+  GRAMMAR:            --> "kl" number
+  GRAMMAR:            --> "dh" number
+  GRAMMAR:            --> "dl" number
+  GRAMMAR:            -->
 */
-fn parse_operand(s: &str) -> Result<(u8, &str)> {
-    parse_number(s)
+fn parse_selection<'a>(s: &'a str, repeat: &Repeat) -> Result<(Selection, &'a str)> {
+    let (make, keep): (fn(u32) -> Selection, bool) = if s.starts_with("kh") {
+        (Selection::KeepHighest, true)
+    } else if s.starts_with("kl") {
+        (Selection::KeepLowest, true)
+    } else if s.starts_with("dh") {
+        (Selection::DropHighest, false)
+    } else if s.starts_with("dl") {
+        (Selection::DropLowest, false)
+    } else {
+        return Ok((Selection::All, s));
+    };
+
+    let (count, rest) = parse_number(&s[2..])?;
+    if keep && (count == 0 || count > repeat.number) {
+        return Err(syntax_error(
+            Error::InvalidSelection(format!("cannot keep {} of {} dice", count, repeat.number)),
+            s,
+            s.len() - rest.len(),
+        ));
+    }
+    if !keep && (count == 0 || count >= repeat.number) {
+        return Err(syntax_error(
+            Error::InvalidSelection(format!("cannot drop {} of {} dice", count, repeat.number)),
+            s,
+            s.len() - rest.len(),
+        ));
+    }
+    Ok((make(count), rest))
 }
 
 /*
+  An 'E' right after a die is read by parse_explode() as that die's spec, so the directive
+  needs a space before it when the code ends with a die: "d6xd6 E", not "d6xd6E".
+
   GRAMMAR: directives --> 'E'
+  GRAMMAR:            --> 'N'
   GRAMMAR:            -->
 */
 fn parse_directives(s: &str) -> Result<(Directives, &str)> {
     if let Some(rest) = s.strip_prefix('E') {
-        Ok((Directives { explode: true }, rest))
+        Ok((
+            Directives {
+                explode: true,
+                ..Directives::default()
+            },
+            rest,
+        ))
+    } else if let Some(rest) = s.strip_prefix('N') {
+        Ok((
+            Directives {
+                never_explode: true,
+                ..Directives::default()
+            },
+            rest,
+        ))
     } else {
         Ok((Directives::default(), s))
     }
 }
 
+/*
+  GRAMMAR: target     --> '<' compare number rules
+  GRAMMAR:            --> '>' compare number rules
+  GRAMMAR:            --> '=' compare number rules
+  GRAMMAR:            -->
+  GRAMMAR: rules      --> 'f' rules
+  GRAMMAR:            --> 's' rules
+  GRAMMAR:            -->
+*/
+fn parse_target(s: &str) -> Result<(Option<Target>, &str)> {
+    // Unlike a reroll, the comparison is required: it's what makes the Dice a pool.
+    if !s.starts_with(['<', '>', '=']) {
+        return Ok((None, s));
+    }
+
+    let (compare, rest) = parse_compare(s);
+    let (number, mut rest) = parse_number(rest)?;
+    let mut target = Target {
+        compare,
+        number,
+        ones_fail: false,
+        max_succeeds: false,
+    };
+    loop {
+        if let Some(r) = rest.strip_prefix('f') {
+            target.ones_fail = true;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('s') {
+            target.max_succeeds = true;
+            rest = r;
+        } else {
+            return Ok((Some(target), rest));
+        }
+    }
+}
+
+// The length in bytes of the first character of `s`, if there is one.
+fn char_len(s: &str) -> usize {
+    s.chars().next().map_or(0, char::len_utf8)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    // The parse error, without where it happened.
+    fn error<T: std::fmt::Debug>(result: Result<T>) -> Error {
+        match result.unwrap_err() {
+            Error::Syntax(err) => err.error,
+            err => err,
+        }
+    }
+
+    fn d6() -> Expr {
+        Expr::Dice(Dice::default())
+    }
+
     #[test]
     fn test_parse_diecode() {
         let diecode = parse_diecode("d6").unwrap();
-        assert_eq!(1, diecode.factors.len());
+        assert_eq!(1, diecode.expr.dice().len());
 
         let diecode = parse_diecode("d6xd6xd6").unwrap();
-        assert_eq!(3, diecode.factors.len());
+        assert_eq!(3, diecode.expr.dice().len());
+
+        let err = error(parse_diecode("d6+1)"));
+        assert!(matches!(err, Error::UnexpectedEOL(_)));
     }
 
     #[test]
@@ -241,47 +1063,210 @@ mod test {
         assert_eq!(
             diecode,
             DieCode {
-                factors: vec![
-                    Factor::default(),
-                    Factor {
-                        repeat: Repeat { number: 2 },
-                        modifier: Modifier::Plus(1),
-                        ..Factor::default()
-                    },
-                    Factor {
-                        sides: 3,
-                        modifier: Modifier::Minus(2),
-                        ..Factor::default()
-                    },
-                ],
-                directives: Directives { explode: true },
+                expr: Expr::binary(
+                    BinaryOp::Subtract,
+                    Expr::binary(
+                        BinaryOp::Add,
+                        Expr::binary(
+                            BinaryOp::Multiply,
+                            d6(),
+                            Expr::Dice(Dice {
+                                repeat: Repeat { number: 2 },
+                                ..Dice::default()
+                            }),
+                        ),
+                        Expr::binary(
+                            BinaryOp::Multiply,
+                            Expr::Constant(1),
+                            Expr::Dice(Dice {
+                                sides: Sides::Die(3),
+                                ..Dice::default()
+                            }),
+                        ),
+                    ),
+                    Expr::Constant(2),
+                ),
+                directives: Directives {
+                    explode: true,
+                    ..Directives::default()
+                },
             }
         )
     }
 
     #[test]
-    fn test_parse_factor() {
-        let (factor, rest) = parse_factor("d6").unwrap();
+    fn test_parse_expr() {
+        let (expr, rest) = parse_expr("(d6+1)x2").unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(
+                BinaryOp::Multiply,
+                Expr::binary(BinaryOp::Add, d6(), Expr::Constant(1)),
+                Expr::Constant(2),
+            )
+        );
+        assert_eq!(rest, "");
+
+        let (expr, rest) = parse_expr("10 - d6 E").unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(BinaryOp::Subtract, Expr::Constant(10), d6())
+        );
+        assert_eq!(rest, " E");
+
+        let (expr, _) = parse_expr("-d6*3").unwrap();
         assert_eq!(
-            factor,
-            Factor {
+            expr,
+            Expr::binary(
+                BinaryOp::Multiply,
+                Expr::Negate(Box::new(d6())),
+                Expr::Constant(3)
+            )
+        );
+
+        let (expr, _) = parse_expr("((d6))").unwrap();
+        assert_eq!(expr, d6());
+
+        let err = error(parse_expr("(d6+1"));
+        assert!(matches!(err, Error::UnexpectedEndOfString(_)));
+
+        let err = error(parse_expr("(d6+1]"));
+        assert!(matches!(err, Error::UnexpectedChar(')', _)));
+
+        let err = error(parse_expr("2d6+"));
+        assert!(matches!(err, Error::UnexpectedEndOfString(_)));
+    }
+
+    #[test]
+    fn test_parse_division() {
+        let (expr, rest) = parse_expr("d6/2+1").unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(
+                BinaryOp::Add,
+                Expr::binary(BinaryOp::Divide(Rounding::Down), d6(), Expr::Constant(2)),
+                Expr::Constant(1)
+            )
+        );
+        assert_eq!(rest, "");
+
+        let (expr, _) = parse_expr("d6/^2").unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(BinaryOp::Divide(Rounding::Up), d6(), Expr::Constant(2))
+        );
+
+        let (expr, _) = parse_expr("d6/~2").unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(BinaryOp::Divide(Rounding::Nearest), d6(), Expr::Constant(2))
+        );
+    }
+
+    #[test]
+    fn test_rounding() {
+        assert_eq!(Rounding::Down.divide(7, 2).unwrap(), 3);
+        assert_eq!(Rounding::Up.divide(7, 2).unwrap(), 4);
+        assert_eq!(Rounding::Nearest.divide(7, 2).unwrap(), 4);
+        assert_eq!(Rounding::Nearest.divide(7, 3).unwrap(), 2);
+
+        assert_eq!(Rounding::Down.divide(-7, 2).unwrap(), -4);
+        assert_eq!(Rounding::Up.divide(-7, 2).unwrap(), -3);
+        assert_eq!(Rounding::Nearest.divide(-7, 2).unwrap(), -3);
+        assert_eq!(Rounding::Down.divide(6, -4).unwrap(), -2);
+
+        assert!(matches!(
+            Rounding::Down.divide(6, 0).unwrap_err(),
+            Error::DivideByZero
+        ));
+    }
+
+    #[test]
+    fn test_parse_atom() {
+        let (expr, rest) = parse_atom("12+").unwrap();
+        assert_eq!(expr, Expr::Constant(12));
+        assert_eq!(rest, "+");
+
+        let (expr, rest) = parse_atom("2d6+").unwrap();
+        assert_eq!(
+            expr,
+            Expr::Dice(Dice {
+                repeat: Repeat { number: 2 },
+                ..Dice::default()
+            })
+        );
+        assert_eq!(rest, "+");
+
+        let err = error(parse_atom("x"));
+        assert!(matches!(err, Error::ExpectedValue(_)));
+    }
+
+    #[test]
+    fn test_parse_dice() {
+        let (dice, rest) = parse_dice("d6").unwrap();
+        assert_eq!(
+            dice,
+            Dice {
                 repeat: Default::default(),
-                sides: 6,
-                modifier: Default::default(),
+                sides: Sides::Die(6),
+                reroll: None,
+                explode: None,
+                selection: Default::default(),
+                target: None,
             }
         );
         assert_eq!(rest, "");
 
-        let (factor, rest) = parse_factor("3d12-3E").unwrap();
+        let (dice, rest) = parse_dice("3d12-3E").unwrap();
         assert_eq!(
-            factor,
-            Factor {
+            dice,
+            Dice {
                 repeat: Repeat { number: 3 },
-                sides: 12,
-                modifier: Modifier::Minus(3),
+                sides: Sides::Die(12),
+                reroll: None,
+                explode: None,
+                selection: Default::default(),
+                target: None,
             }
         );
-        assert_eq!(rest, "E");
+        assert_eq!(rest, "-3E");
+
+        let (dice, rest) = parse_dice("2d6E-1").unwrap();
+        assert_eq!(
+            dice,
+            Dice {
+                repeat: Repeat { number: 2 },
+                explode: Some(Explode::default()),
+                ..Dice::default()
+            }
+        );
+        assert_eq!(rest, "-1");
+
+        let (dice, rest) = parse_dice("2d6r1").unwrap();
+        assert_eq!(
+            dice,
+            Dice {
+                repeat: Repeat { number: 2 },
+                reroll: Some(Reroll {
+                    compare: Compare::Equal,
+                    target: 1,
+                    once: true
+                }),
+                ..Dice::default()
+            }
+        );
+        assert_eq!(rest, "");
+
+        let (dice, rest) = parse_dice("4d6kh3+1").unwrap();
+        assert_eq!(
+            dice,
+            Dice {
+                repeat: Repeat { number: 4 },
+                selection: Selection::KeepHighest(3),
+                ..Dice::default()
+            }
+        );
+        assert_eq!(rest, "+1");
     }
 
     #[test]
@@ -294,27 +1279,76 @@ mod test {
         assert_eq!(repeat, Repeat { number: 1 });
         assert_eq!(rest, "d6");
 
-        let err = parse_repeat("0d6").unwrap_err();
+        let err = error(parse_repeat("0d6"));
         assert!(matches!(err, Error::ZeroRepeats));
     }
 
     #[test]
     fn test_parse_sides() {
         let (sides, rest) = parse_sides("3").unwrap();
-        assert_eq!(sides, 3);
+        assert_eq!(sides, Sides::Die(3));
         assert_eq!(rest, "");
 
         let (sides, rest) = parse_sides("6+3").unwrap();
-        assert_eq!(sides, 6);
+        assert_eq!(sides, Sides::Die(6));
         assert_eq!(rest, "+3");
 
-        let err = parse_sides("0").unwrap_err();
+        let (sides, rest) = parse_sides("20x").unwrap();
+        assert_eq!(sides, Sides::Die(20));
+        assert_eq!(rest, "x");
+
+        let err = error(parse_sides("0"));
         assert!(matches!(err, Error::ZeroOrOneSide));
 
-        let err = parse_sides("1").unwrap_err();
+        let err = error(parse_sides("1"));
         assert!(matches!(err, Error::ZeroOrOneSide));
     }
 
+    #[test]
+    fn test_parse_digit_sides() {
+        let (sides, rest) = parse_sides("66+1").unwrap();
+        assert_eq!(sides, Sides::Digits(vec![6, 6]));
+        assert_eq!(rest, "+1");
+
+        let (sides, rest) = parse_sides("6666").unwrap();
+        assert_eq!(sides, Sides::Digits(vec![6, 6, 6, 6]));
+        assert_eq!(rest, "");
+
+        let (sides, rest) = parse_sides("10d10xd6").unwrap();
+        assert_eq!(sides, Sides::Digits(vec![10, 10]));
+        assert_eq!(rest, "xd6");
+
+        let (sides, _) = parse_sides("6d6").unwrap();
+        assert_eq!(sides, Sides::Digits(vec![6, 6]));
+
+        let err = error(parse_sides("66666"));
+        assert!(matches!(err, Error::InvalidDigitDie(_)));
+
+        let err = error(parse_sides("12d6"));
+        assert!(matches!(err, Error::InvalidDigitDie(_)));
+
+        let err = error(parse_sides("6d1"));
+        assert!(matches!(err, Error::ZeroOrOneSide));
+    }
+
+    #[test]
+    fn test_without_digits() {
+        assert_eq!(
+            Sides::Digits(vec![8, 8]).without_digits().unwrap(),
+            Sides::Die(88)
+        );
+        assert_eq!(
+            Sides::Digits(vec![10, 10]).without_digits().unwrap(),
+            Sides::Digits(vec![10, 10])
+        );
+        assert_eq!(Sides::Die(6).without_digits().unwrap(), Sides::Die(6));
+
+        assert_eq!(
+            Sides::Digits(vec![6, 6, 6]).without_digits().unwrap(),
+            Sides::Die(666)
+        );
+    }
+
     #[test]
     fn test_parse_number() {
         let (number, rest) = parse_number("64REST").unwrap();
@@ -325,45 +1359,256 @@ mod test {
         assert_eq!(number, 83);
         assert_eq!(rest, "");
 
-        let err = parse_number("888").unwrap_err();
+        let (number, _) = parse_number("4294967295").unwrap();
+        assert_eq!(number, u32::MAX);
+
+        let err = error(parse_number("4294967296"));
         assert!(matches!(err, Error::ParseNumberError(_, _)));
 
-        let err = parse_number("MISSING").unwrap_err();
+        let err = error(parse_number("MISSING"));
         assert!(matches!(err, Error::ParseNumberError(_, _)));
     }
 
     #[test]
-    fn test_parse_modifier() {
-        let (modifier, rest) = parse_modifier("NONE").unwrap();
-        assert_eq!(modifier, Modifier::None);
-        assert_eq!(rest, "NONE");
-
-        // PLUS, AT END
-        let (modifier, rest) = parse_modifier("+38").unwrap();
-        assert_eq!(modifier, Modifier::Plus(38));
+    fn test_parse_reroll() {
+        let d6 = Sides::Die(6);
+
+        let (reroll, rest) = parse_reroll("+1", &d6).unwrap();
+        assert_eq!(reroll, None);
+        assert_eq!(rest, "+1");
+
+        let (reroll, rest) = parse_reroll("r1+1", &d6).unwrap();
+        assert_eq!(
+            reroll,
+            Some(Reroll {
+                compare: Compare::Equal,
+                target: 1,
+                once: true
+            })
+        );
+        assert_eq!(rest, "+1");
+
+        let (reroll, rest) = parse_reroll("R<3", &d6).unwrap();
+        assert_eq!(
+            reroll,
+            Some(Reroll {
+                compare: Compare::Less,
+                target: 3,
+                once: false
+            })
+        );
         assert_eq!(rest, "");
 
-        // MINUS, WITH REST
-        let (modifier, rest) = parse_modifier("-83REST").unwrap();
-        assert_eq!(modifier, Modifier::Minus(83));
-        assert_eq!(rest, "REST");
+        // Every face matching is fine if it only happens once.
+        let (reroll, _) = parse_reroll("r<=6", &d6).unwrap();
+        assert_eq!(reroll.unwrap().compare, Compare::LessOrEqual);
+
+        let err = error(parse_reroll("R<=6", &d6));
+        assert!(matches!(err, Error::InvalidReroll(_)));
+
+        let err = error(parse_reroll("R>0", &Sides::Digits(vec![6, 6])));
+        assert!(matches!(err, Error::InvalidReroll(_)));
+
+        let err = error(parse_reroll("r<", &d6));
+        assert!(matches!(err, Error::ParseNumberError(_, _)));
     }
 
     #[test]
-    fn test_parse_operand() {
-        let (operand, rest) = parse_operand("123REST").unwrap();
-        assert_eq!(operand, 123);
-        assert_eq!(rest, "REST");
+    fn test_parse_compare() {
+        assert_eq!(parse_compare("<=3"), (Compare::LessOrEqual, "3"));
+        assert_eq!(parse_compare(">=3"), (Compare::GreaterOrEqual, "3"));
+        assert_eq!(parse_compare("<3"), (Compare::Less, "3"));
+        assert_eq!(parse_compare(">3"), (Compare::Greater, "3"));
+        assert_eq!(parse_compare("=3"), (Compare::Equal, "3"));
+        assert_eq!(parse_compare("3"), (Compare::Equal, "3"));
+    }
+
+    #[test]
+    fn test_parse_explode() {
+        let d6 = Sides::Die(6);
+        let d8 = Sides::Die(8);
+
+        let (explode, rest) = parse_explode("+1", &d6, None).unwrap();
+        assert_eq!(explode, None);
+        assert_eq!(rest, "+1");
+
+        let (explode, rest) = parse_explode("E+1", &d6, None).unwrap();
+        assert_eq!(explode, Some(Explode::default()));
+        assert_eq!(rest, "+1");
+
+        let (explode, rest) = parse_explode("E7+", &d8, None).unwrap();
+        assert_eq!(
+            explode,
+            Some(Explode {
+                style: ExplodeStyle::Compound,
+                threshold: Some(7)
+            })
+        );
+        assert_eq!(rest, "");
+
+        let (explode, _) = parse_explode("!!", &d6, None).unwrap();
+        assert_eq!(explode.unwrap().style, ExplodeStyle::Compound);
+
+        let (explode, _) = parse_explode("!p", &d6, None).unwrap();
+        assert_eq!(explode.unwrap().style, ExplodeStyle::Penetrate);
+
+        let (explode, rest) = parse_explode("!5+kh1", &d6, None).unwrap();
+        assert_eq!(
+            explode,
+            Some(Explode {
+                style: ExplodeStyle::Separate,
+                threshold: Some(5)
+            })
+        );
+        assert_eq!(rest, "kh1");
+
+        let err = error(parse_explode("E5", &d6, None));
+        assert!(matches!(err, Error::UnexpectedChar('+', _)));
+
+        let err = error(parse_explode("E1+", &d6, None));
+        assert!(matches!(err, Error::InvalidExplosion(_)));
+
+        let err = error(parse_explode("E7+", &d6, None));
+        assert!(matches!(err, Error::InvalidExplosion(_)));
+
+        let err = error(parse_explode("E", &Sides::Digits(vec![6, 6]), None));
+        assert!(matches!(err, Error::InvalidExplosion(_)));
+        // Rerolling until a die stops matching must leave a face that doesn't explode.
+        let until = |compare, target| Reroll {
+            compare,
+            target,
+            once: false,
+        };
+        let err = error(parse_explode("E", &d6, Some(until(Compare::Less, 6))));
+        assert!(matches!(err, Error::InvalidExplosion(_)));
+        let err = error(parse_explode(
+            "!",
+            &Sides::Die(2),
+            Some(until(Compare::Equal, 1)),
+        ));
+        assert!(matches!(err, Error::InvalidExplosion(_)));
+        assert!(parse_explode("E", &d6, Some(until(Compare::Less, 5))).is_ok());
+        let once = Reroll {
+            once: true,
+            ..until(Compare::Less, 6)
+        };
+        assert!(parse_explode("E", &d6, Some(once)).is_ok());
+        assert!("d6R<6E".parse::<DieCode>().is_err());
+    }
 
-        // TOO BIG
-        let err = parse_operand("1234REST").unwrap_err();
+    #[test]
+    fn test_explodes() {
+        let explode = Explode::default();
+        assert!(explode.explodes(6, 6));
+        assert!(!explode.explodes(5, 6));
+
+        let explode = Explode {
+            threshold: Some(7),
+            ..Explode::default()
+        };
+        assert!(explode.explodes(7, 8));
+        assert!(!explode.explodes(6, 8));
+    }
+
+    #[test]
+    fn test_parse_selection() {
+        let four = Repeat { number: 4 };
+
+        let (selection, rest) = parse_selection("+1", &four).unwrap();
+        assert_eq!(selection, Selection::All);
+        assert_eq!(rest, "+1");
+
+        let (selection, rest) = parse_selection("kh3+1", &four).unwrap();
+        assert_eq!(selection, Selection::KeepHighest(3));
+        assert_eq!(rest, "+1");
+
+        let (selection, _) = parse_selection("kl4", &four).unwrap();
+        assert_eq!(selection, Selection::KeepLowest(4));
+
+        let (selection, _) = parse_selection("dh1", &four).unwrap();
+        assert_eq!(selection, Selection::DropHighest(1));
+
+        let (selection, _) = parse_selection("dl3", &four).unwrap();
+        assert_eq!(selection, Selection::DropLowest(3));
+
+        let err = error(parse_selection("kh5", &four));
+        assert!(matches!(err, Error::InvalidSelection(_)));
+
+        let err = error(parse_selection("kh0", &four));
+        assert!(matches!(err, Error::InvalidSelection(_)));
+
+        let err = error(parse_selection("dl4", &four));
+        assert!(matches!(err, Error::InvalidSelection(_)));
+
+        let err = error(parse_selection("kh", &four));
         assert!(matches!(err, Error::ParseNumberError(_, _)));
+    }
+
+    #[test]
+    fn test_parse_target() {
+        let (target, rest) = parse_target("+1").unwrap();
+        assert_eq!(target, None);
+        assert_eq!(rest, "+1");
+
+        let (target, rest) = parse_target(">=5+1").unwrap();
+        assert_eq!(
+            target,
+            Some(Target {
+                compare: Compare::GreaterOrEqual,
+                number: 5,
+                ones_fail: false,
+                max_succeeds: false,
+            })
+        );
+        assert_eq!(rest, "+1");
+
+        let (target, rest) = parse_target("<3fs").unwrap();
+        assert_eq!(
+            target,
+            Some(Target {
+                compare: Compare::Less,
+                number: 3,
+                ones_fail: true,
+                max_succeeds: true,
+            })
+        );
+        assert_eq!(rest, "");
 
-        // MISSING
-        let err = parse_operand("MISSING").unwrap_err();
+        let err = error(parse_target(">="));
         assert!(matches!(err, Error::ParseNumberError(_, _)));
     }
 
+    #[test]
+    fn test_target_succeeds() {
+        let target = Target {
+            compare: Compare::GreaterOrEqual,
+            number: 5,
+            ones_fail: true,
+            max_succeeds: true,
+        };
+        assert!(target.succeeds(5, false, false));
+        assert!(!target.succeeds(4, false, false));
+        assert!(!target.succeeds(7, true, false));
+        assert!(target.succeeds(3, false, true));
+
+        let target = Target {
+            ones_fail: false,
+            max_succeeds: false,
+            ..target
+        };
+        assert!(target.succeeds(7, true, false));
+        assert!(!target.succeeds(3, false, true));
+    }
+
+    #[test]
+    fn test_selection_kept() {
+        assert_eq!(Selection::All.kept(4), (4, true));
+        assert_eq!(Selection::KeepHighest(3).kept(4), (3, true));
+        assert_eq!(Selection::KeepLowest(1).kept(2), (1, false));
+        assert_eq!(Selection::DropHighest(1).kept(3), (2, false));
+        assert_eq!(Selection::DropLowest(1).kept(3), (2, true));
+    }
+
     #[test]
     fn test_parse_directives() {
         let (directives, rest) = parse_directives("").unwrap();
@@ -379,5 +1624,88 @@ mod test {
             }
         );
         assert_eq!(rest, "EE");
+
+        let (directives, rest) = parse_directives("N").unwrap();
+        assert_eq!(
+            directives,
+            Directives {
+                never_explode: true,
+                ..Directives::default()
+            }
+        );
+        assert_eq!(rest, "");
+    }
+
+    #[test]
+    fn test_explode_binding() {
+        // An 'E' straight after a die is that die's own spec, so only the second d6 explodes.
+        let diecode = parse_diecode("d6xd6E").unwrap();
+        let explodes: Vec<bool> = diecode
+            .expr
+            .dice()
+            .iter()
+            .map(|dice| dice.explode.is_some())
+            .collect();
+        assert_eq!(explodes, vec![false, true]);
+        assert_eq!(diecode.directives, Directives::default());
+
+        // Set off by a space, it is the directive, and every d6 explodes.
+        let diecode = parse_diecode("d6xd6 E").unwrap();
+        assert!(diecode
+            .expr
+            .dice()
+            .iter()
+            .all(|dice| dice.explode.is_none()));
+        assert!(diecode.directives.explode);
+
+        // After anything but a die, there is nothing else for it to be.
+        let diecode = parse_diecode("(d6xd6)E").unwrap();
+        assert!(diecode.directives.explode);
+    }
+
+    #[test]
+    fn test_display() {
+        // Codes that are already written the way they are displayed.
+        for code in [
+            "d6",
+            "2d6+1",
+            "d6xd6",
+            "d66",
+            "d10d10",
+            "d6d10",
+            "4d6kh3",
+            "3d6dl1",
+            "d6r1",
+            "d6R<3",
+            "2d8E7+",
+            "3d6!kl1",
+            "d6!p",
+            "5d6>=5fs",
+            "d66r<=20>40",
+            "(d6+1)x2",
+            "10-d6",
+            "d6-(2-1)",
+            "-(d6+1)",
+            "d6/^2",
+            "d6xd6 E",
+            "2d6 N",
+        ] {
+            assert_eq!(parse_diecode(code).unwrap().to_string(), code);
+        }
+
+        // Codes that display differently, but mean the same thing.
+        for (code, displayed) in [
+            ("1d6", "d6"),
+            ("d6r=1", "d6r1"),
+            ("d6!!", "d6E"),
+            ("((d6))", "d6"),
+            ("2d6 + 1", "2d6+1"),
+            ("2*d6", "2xd6"),
+            ("d6xd6E", "d6xd6E"),
+        ] {
+            let parsed = parse_diecode(code).unwrap();
+            assert_eq!(parsed.to_string(), displayed);
+            assert_eq!(parse_diecode(displayed).unwrap(), parsed);
+        }
     }
 }
@@ -1,10 +1,46 @@
 use thiserror::Error;
 
 mod executor;
-use executor::execute;
+use executor::execute_observed;
+pub use executor::RollOptions;
+
+mod diagnostic;
+pub use diagnostic::SyntaxError;
+
+pub mod grammar;
+pub use grammar::DieCode;
+
+mod canonical;
+
+#[cfg(feature = "serde")]
+pub mod serialize;
+
+mod distribution;
+pub use distribution::{distribution, Distribution};
+
+mod summary;
+pub use summary::Summary;
+
+pub mod query;
+pub use query::Query;
+
+mod observer;
+pub use observer::{Observer, SpewObserver};
 
-mod grammar;
 mod roller;
+pub use roller::{ManualRoller, RandRoller, RecordingRoller, ReplayRoller, RngRoller, Roller};
+
+mod rollresult;
+pub use rollresult::{DiceResult, DieResult, RollNode, RollResult, Rolls};
+
+pub mod table;
+pub use table::Tables;
+
+mod stats;
+pub use stats::Stats;
+
+mod batch;
+pub use batch::{batches, Batch};
 
 pub type Result<T> = std::result::Result<T, FourADError>;
 
@@ -19,27 +55,80 @@ pub enum FourADError {
     #[error("Could not parse a number")]
     ParseNumberError(String, std::num::ParseIntError),
 
+    #[error("Expected a number, dice or '(' but found '{0}'")]
+    ExpectedValue(String),
+
     #[error("Dice cannot have zero sides or one side.")]
     ZeroOrOneSide,
 
+    #[error("Invalid digit die: {0}.")]
+    InvalidDigitDie(String),
+
+    #[error("Invalid reroll: {0}.")]
+    InvalidReroll(String),
+
+    #[error("Invalid explosion: {0}.")]
+    InvalidExplosion(String),
+
+    #[error("Invalid keep/drop: {0}.")]
+    InvalidSelection(String),
+
     #[error("Repeating zero times is not allowed")]
     ZeroRepeats,
 
-    #[error("An I/O error occurred")]
+    #[error("Invalid table: {0}.")]
+    InvalidTable(String),
+
+    #[error("Invalid query: {0}.")]
+    InvalidQuery(String),
+
+    #[error("Invalid repeat: {0}.")]
+    InvalidRepeat(String),
+
+    #[error("Invalid replay: {0}.")]
+    InvalidReplay(String),
+
+    #[error("Line {0} of the tables: {1}")]
+    TableLine(usize, Box<FourADError>),
+
+    #[error("The result is too large")]
+    Overflow,
+
+    #[error("There are too many possible results to work out exactly")]
+    TooComplex,
+
+    #[error("Division by zero")]
+    DivideByZero,
+
+    #[error("An I/O error occurred: {0}")]
     IO(#[from] std::io::Error),
 
     #[error("Unexpected input at end of line: {0}")]
     UnexpectedEOL(String),
 
-    #[error("Error: (0)")]
+    #[error("{0}")]
+    Syntax(Box<SyntaxError>),
+
+    #[error("{0}")]
     GeneralError(String),
 }
 
 pub type Error = FourADError;
 
-pub fn roll(diecode: &str, explode: bool, force_66: bool) -> Result<i16> {
-    Ok(execute(diecode.parse()?, explode, force_66))
+// Rolls `diecode` with any source of dice: a seeded RandRoller, or the caller's own.
+pub fn roll(diecode: &str, options: &RollOptions, roller: &mut impl Roller) -> Result<RollResult> {
+    roll_observed(diecode, options, roller, &mut ())
+}
+
+// Rolls `diecode`, telling `observer` about every die as it is rolled.
+pub fn roll_observed(
+    diecode: &str,
+    options: &RollOptions,
+    roller: &mut impl Roller,
+    observer: &mut impl Observer,
+) -> Result<RollResult> {
+    execute_observed(diecode.parse()?, options, roller, observer)
 }
 
 // *_fa functions are exported for the macros to use.
-pub use tools::{quiet_fa, set_level, spew_fa, verbose_fa, SpewLevel};
+pub use tools::{error_fa, level, quiet_fa, set_level, spew_fa, verbose_fa, SpewLevel};
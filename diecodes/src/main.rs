@@ -1,8 +1,14 @@
 use argh::FromArgs;
-use std::io::{BufRead, BufReader};
-use tools::{quiet, spew, SpewLevel};
+use fourad::{
+    ManualRoller, RandRoller, RecordingRoller, ReplayRoller, RollOptions, Roller, SpewObserver,
+};
+use std::io::IsTerminal;
+use tools::{error, quiet, spew, verbose, Sink, SpewLevel};
 
-// TODO: improve output formatting.
+mod output;
+use output::{Format, Record, Writer};
+
+mod repl;
 
 #[derive(FromArgs)]
 /// A dice roller for _Four Against Darkness_
@@ -14,9 +20,33 @@ struct Args {
     #[argh(switch, short = 'X')]
     explode: bool,
 
-    /// if set, d66 will be treated as a 66-sided die
+    /// if set, digit dice are treated as ordinary dice (d66 will be a 66-sided die)
     #[argh(switch)]
-    force_66: bool,
+    no_digit_dice: bool,
+
+    /// the most times a single die may explode (no limit if missing)
+    #[argh(option)]
+    max_explosions: Option<u8>,
+
+    /// seed for the random roller, to replay an earlier session
+    #[argh(option)]
+    seed: Option<u64>,
+
+    /// how to print results: text (the default), json, jsonl or csv
+    #[argh(option, default = "Format::Text")]
+    format: Format,
+
+    /// if set, ask for the value of each die instead of rolling it (for physical dice)
+    #[argh(switch)]
+    manual: bool,
+
+    /// write every die rolled to this file, to replay later
+    #[argh(option)]
+    record: Option<String>,
+
+    /// take the dice from a file written by --record, instead of rolling them
+    #[argh(option)]
+    replay: Option<String>,
 
     /// if set, run with minimal output
     #[argh(switch, short = 'q')]
@@ -25,18 +55,98 @@ struct Args {
     /// if set, run with lots of output
     #[argh(switch, short = 'v')]
     verbose: bool,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
 }
 
-fn output_code(s: &str, explode: bool, print_codes: bool, force_66: bool) -> fourad::Result<()> {
-    if print_codes {
-        spew!("{}", s);
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Table(TableArgs),
+    Stats(StatsArgs),
+    Info(InfoArgs),
+    Prob(ProbArgs),
+}
+
+#[derive(FromArgs)]
+/// Roll on a random table
+#[argh(subcommand, name = "table")]
+struct TableArgs {
+    #[argh(positional)]
+    name: String,
+
+    /// the file holding the tables
+    #[argh(option, short = 'f', default = "String::from(\"fourad.tables\")")]
+    file: String,
+}
+
+#[derive(FromArgs)]
+/// Roll a code many times and summarise the totals
+#[argh(subcommand, name = "stats")]
+struct StatsArgs {
+    #[argh(positional)]
+    code: String,
+
+    /// how many times to roll
+    #[argh(option, short = 'n', default = "10000")]
+    count: usize,
+}
+
+#[derive(FromArgs)]
+/// Show the range, mean and variance of a code without rolling it
+#[argh(subcommand, name = "info")]
+struct InfoArgs {
+    #[argh(positional)]
+    code: String,
+}
+
+#[derive(FromArgs)]
+/// Work out the chance of a query like "P(2d6+1 >= 8)"
+#[argh(subcommand, name = "prob")]
+struct ProbArgs {
+    #[argh(positional)]
+    query: String,
+}
+
+// Rolls every code in `input`, which can hold several ("d20+5, 2d6+3") and repeats ("6#3d6"),
+// and prints the totals. Each code is echoed first, after `label`, if `echo` is set or there is
+// more than one roll.
+fn output_codes(
+    input: &str,
+    label: Option<&str>,
+    echo: bool,
+    options: &RollOptions,
+    roller: &mut impl Roller,
+) -> fourad::Result<Vec<i64>> {
+    let batches = fourad::batches(input)?;
+    let echo = echo || batches.len() > 1 || batches.iter().any(|batch| batch.times > 1);
+    let mut totals = vec![];
+    for batch in batches {
+        match label {
+            Some(label) if echo => spew!("{}: {}", label, batch),
+            None if echo => spew!("{}", batch),
+            _ => {}
+        }
+        for _ in 0..batch.times {
+            totals.push(output_code(batch.code, options, roller)?);
+        }
+        if echo {
+            spew!("")
+        }
     }
-    // TODO: this prints too much when "quiet".
-    quiet!("===> {}", fourad::roll(s, explode, force_66)?);
-    if print_codes {
-        spew!("")
+    Ok(totals)
+}
+
+fn output_code(s: &str, options: &RollOptions, roller: &mut impl Roller) -> fourad::Result<i64> {
+    let result = fourad::roll_observed(s, options, roller, &mut SpewObserver)?;
+    verbose!("{}", result);
+    if fourad::level() == SpewLevel::QUIET {
+        quiet!("{}", result.total);
+    } else {
+        spew!("===> {}", result.total);
     }
-    Ok(())
+    Ok(result.total)
 }
 
 fn set_spew_level(args: &Args) -> fourad::Result<()> {
@@ -56,19 +166,166 @@ fn set_spew_level(args: &Args) -> fourad::Result<()> {
     Ok(())
 }
 
-fn main() -> fourad::Result<()> {
-    let args: Args = argh::from_env();
+fn main() {
+    if let Err(err) = run(argh::from_env()) {
+        error!("{}", err);
+        std::process::exit(1);
+    }
+}
 
+fn run(args: Args) -> fourad::Result<()> {
     set_spew_level(&args)?;
+    // Traces and errors go to stderr, so stdout only has the results (like the records of
+    // --format json), even with -v.
+    tools::set_level_sink(SpewLevel::VERBOSE, Sink::Stderr);
+    tools::set_level_sink(SpewLevel::ERROR, Sink::Stderr);
+
+    let options = RollOptions {
+        explode: args.explode,
+        no_digit_dice: args.no_digit_dice,
+        max_explosions: args.max_explosions,
+    };
+
+    if args.manual && args.replay.is_some() {
+        return Err(fourad::Error::GeneralError(
+            "--manual and --replay are not compatible.".to_string(),
+        ));
+    }
+    let mut roller: Box<dyn Roller> = if args.manual {
+        // The dice values come from stdin, so the codes can't as well.
+        if args.codes.is_empty() && args.command.is_none() && !std::io::stdin().is_terminal() {
+            return Err(fourad::Error::GeneralError(
+                "--manual reads dice from stdin, so the codes must be given as arguments."
+                    .to_string(),
+            ));
+        }
+        Box::new(ManualRoller::stdio())
+    } else if let Some(replay) = &args.replay {
+        Box::new(ReplayRoller::load(replay)?)
+    } else {
+        let roller = args.seed.map(RandRoller::from_seed).unwrap_or_default();
+        verbose!("Seed: {}", roller.seed());
+        Box::new(roller)
+    };
+    if let Some(record) = &args.record {
+        roller = Box::new(RecordingRoller::new(roller, std::fs::File::create(record)?));
+    }
+
+    match args.command {
+        Some(Command::Table(table)) => {
+            let tables = fourad::Tables::load(&table.file)?;
+            quiet!("{}", tables.roll(&table.name, &options, &mut roller)?);
+            return Ok(());
+        }
+        Some(Command::Stats(stats)) => {
+            let code = stats.code.parse()?;
+            let stats = fourad::Stats::sample(&code, &options, stats.count, &mut roller)?
+                .ok_or_else(|| {
+                    fourad::Error::GeneralError("stats needs at least one roll".to_string())
+                })?;
+            quiet!("{}", stats);
+            return Ok(());
+        }
+        Some(Command::Info(info)) => {
+            let code: fourad::DieCode = info.code.parse()?;
+            let summary = code.summary(&options)?;
+            spew!("{}", code.canonical());
+            quiet!("{}", summary);
+            spew!(
+                "variance {:.3}, stddev {:.3}",
+                summary.variance,
+                summary.stddev()
+            );
+            return Ok(());
+        }
+        Some(Command::Prob(prob)) => {
+            let query: fourad::Query = prob.query.parse()?;
+            let probability = query.probability(&options)?;
+            if fourad::level() == SpewLevel::QUIET {
+                quiet!("{}", probability);
+            } else {
+                spew!(
+                    "{} = {:.4} ({:.2}%)",
+                    query,
+                    probability,
+                    probability * 100.0
+                );
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if args.format != Format::Text {
+        let mut writer = Writer::new(args.format);
+        if args.codes.is_empty() {
+            tools::process_stdin(|line| {
+                for record in Record::roll_all(line.text, line.label, &options, &mut roller) {
+                    writer.write(&record);
+                }
+                Ok::<_, fourad::Error>(())
+            })?;
+        }
+        for code in &args.codes {
+            for record in Record::roll_all(code, None, &options, &mut roller) {
+                writer.write(&record);
+            }
+        }
+        writer.finish();
+        return Ok(());
+    }
 
     if args.codes.is_empty() {
-        tools::process_stdin(|line| output_code(line, args.explode, true, args.force_66))?;
+        if std::io::stdin().is_terminal() {
+            return repl::run(&options, &mut roller);
+        }
+        let processed = tools::process_stdin(|line| {
+            output_codes(line.text, line.label, true, &options, &mut roller).map(|_| ())
+        })?;
+        if !processed.succeeded() {
+            return Err(fourad::Error::GeneralError(processed.to_string()));
+        }
+        return Ok(());
     }
 
     let print_codes = args.codes.len() > 1;
 
     for code in args.codes {
-        output_code(&code, args.explode, print_codes, args.force_66)?;
+        output_codes(&code, None, print_codes, &options, &mut roller)?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tools::Buffer;
+
+    fn output(input: &str, label: Option<&str>, echo: bool, log: &str) -> (Vec<i64>, String) {
+        let mut roller: ReplayRoller = log.parse().unwrap();
+        let buffer = Buffer::new();
+        tools::set_thread_sink(Some(Sink::Buffer(buffer.clone())));
+        let totals = output_codes(input, label, echo, &RollOptions::default(), &mut roller);
+        tools::set_thread_sink(None);
+        (totals.unwrap(), buffer.take())
+    }
+
+    #[test]
+    fn test_output_codes() {
+        assert_eq!(
+            output("2d6+1", None, false, "d6: 2\nd6: 5\n"),
+            (vec![8], "===> 8\n".to_string())
+        );
+        assert_eq!(
+            output("2d6+1", Some("attack"), true, "d6: 2\nd6: 5\n"),
+            (vec![8], "attack: 2d6+1\n===> 8\n\n".to_string())
+        );
+        assert_eq!(
+            output("3#d6; d4", None, false, "d6: 1\nd6: 2\nd6: 3\nd4: 4\n"),
+            (
+                vec![1, 2, 3, 4],
+                "3#d6\n===> 1\n===> 2\n===> 3\n\nd4\n===> 4\n\n".to_string()
+            )
+        );
+    }
+}
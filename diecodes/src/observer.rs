@@ -0,0 +1,76 @@
+use crate::grammar::Expr;
+use tools::verbose;
+
+// Hears about a roll as the executor works through it, for anything that wants to follow along:
+// a logger, a GUI animating the dice, a statistics collector. Every method does nothing unless
+// it is overridden.
+pub trait Observer {
+    // The first roll of an ordinary die.
+    fn rolled(&mut self, _sides: u32, _die: u32) {}
+
+    // The roll of one digit of a digit die, like each d6 of a d66.
+    fn digit(&mut self, _sides: u32, _die: u32) {}
+
+    // A die, or digit, was rolled again and came up `die` this time.
+    fn rerolled(&mut self, _sides: u32, _die: u32) {}
+
+    // `die` exploded, so another die is coming.
+    fn exploded(&mut self, _sides: u32, _die: u32) {}
+
+    // A constant in the code, like the 2 of d6+2.
+    fn modifier(&mut self, _value: i64) {}
+
+    // The total of a Dice, negation or binary operation, once its parts are all rolled.
+    fn subtotal(&mut self, _expr: &Expr, _value: i64) {}
+}
+
+// For rolling without anyone watching.
+impl Observer for () {}
+
+impl<O: Observer + ?Sized> Observer for &mut O {
+    fn rolled(&mut self, sides: u32, die: u32) {
+        (**self).rolled(sides, die)
+    }
+
+    fn digit(&mut self, sides: u32, die: u32) {
+        (**self).digit(sides, die)
+    }
+
+    fn rerolled(&mut self, sides: u32, die: u32) {
+        (**self).rerolled(sides, die)
+    }
+
+    fn exploded(&mut self, sides: u32, die: u32) {
+        (**self).exploded(sides, die)
+    }
+
+    fn modifier(&mut self, value: i64) {
+        (**self).modifier(value)
+    }
+
+    fn subtotal(&mut self, expr: &Expr, value: i64) {
+        (**self).subtotal(expr, value)
+    }
+}
+
+// Reports each die through verbose!, as fourad -v does.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SpewObserver;
+
+impl Observer for SpewObserver {
+    fn rolled(&mut self, _sides: u32, die: u32) {
+        verbose!("Rolled: {}", die);
+    }
+
+    fn digit(&mut self, _sides: u32, die: u32) {
+        verbose!("Rolled: {}", die);
+    }
+
+    fn rerolled(&mut self, _sides: u32, die: u32) {
+        verbose!("Rerolled: {}", die);
+    }
+
+    fn exploded(&mut self, _sides: u32, _die: u32) {
+        verbose!("Exploded!");
+    }
+}
@@ -0,0 +1,283 @@
+use fourad::{DieCode, RollOptions, Roller};
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+    Text,
+    // One JSON array holding every record, written once all codes are rolled.
+    Json,
+    // One JSON object per line.
+    Jsonl,
+    // A header row, then one row per record.
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!(
+                "unknown format '{}': expected text, json, jsonl or csv",
+                s
+            )),
+        }
+    }
+}
+
+// The outcome of rolling one code, for the machine-readable formats.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Record {
+    pub input: String,
+    // From a "label: code" line of stdin.
+    pub label: Option<String>,
+    pub code: Option<String>,
+    // The value of every die that counts, grouped by the dice they were rolled for.
+    pub dice: Vec<Vec<i64>>,
+    // The dice left out by keep/drop rules, grouped the same way.
+    pub dropped: Vec<Vec<i64>>,
+    pub total: Option<i64>,
+    pub error: Option<String>,
+}
+
+impl Record {
+    pub fn roll(input: &str, options: &RollOptions, roller: &mut impl Roller) -> Record {
+        let mut record = Record {
+            input: input.to_string(),
+            ..Record::default()
+        };
+        let result = input.parse::<DieCode>().and_then(|code| {
+            record.code = Some(code.canonical().to_string());
+            code.roll(options, roller)
+        });
+        match result {
+            Ok(result) => {
+                let values = |dropped: bool| {
+                    result
+                        .dice()
+                        .iter()
+                        .map(|dice| {
+                            dice.dice
+                                .iter()
+                                .filter(|die| die.dropped == dropped)
+                                .map(|die| die.value())
+                                .collect()
+                        })
+                        .collect()
+                };
+                record.dice = values(false);
+                record.dropped = values(true);
+                record.total = Some(result.total);
+            }
+            Err(err) => record.error = Some(error_message(&err)),
+        }
+        record
+    }
+
+    // A record for each roll of each code in an input like "6#3d6, d20", or one with the error
+    // if the input can't be split into codes. Every record gets the same `label`.
+    pub fn roll_all(
+        input: &str,
+        label: Option<&str>,
+        options: &RollOptions,
+        roller: &mut impl Roller,
+    ) -> Vec<Record> {
+        let label = label.map(str::to_string);
+        match fourad::batches(input) {
+            Ok(batches) => batches
+                .iter()
+                .flat_map(|batch| std::iter::repeat_n(batch.code, batch.times as usize))
+                .map(|code| Record {
+                    label: label.clone(),
+                    ..Record::roll(code, options, roller)
+                })
+                .collect(),
+            Err(err) => vec![Record {
+                input: input.to_string(),
+                label,
+                error: Some(error_message(&err)),
+                ..Record::default()
+            }],
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "input": self.input,
+            "label": self.label,
+            "code": self.code,
+            "dice": self.dice,
+            "dropped": self.dropped,
+            "total": self.total,
+            "error": self.error,
+        })
+    }
+
+    fn to_csv(&self) -> String {
+        // Dice are separated by spaces, and each Dice in the code by a ';'.
+        let dice = |dice: &[Vec<i64>]| {
+            dice.iter()
+                .map(|dice| {
+                    dice.iter()
+                        .map(i64::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .collect::<Vec<_>>()
+                .join(";")
+        };
+        [
+            csv_field(&self.input),
+            csv_field(self.label.as_deref().unwrap_or("")),
+            csv_field(self.code.as_deref().unwrap_or("")),
+            csv_field(&dice(&self.dice)),
+            csv_field(&dice(&self.dropped)),
+            self.total
+                .map(|total| total.to_string())
+                .unwrap_or_default(),
+            csv_field(self.error.as_deref().unwrap_or("")),
+        ]
+        .join(",")
+    }
+}
+
+// Syntax errors are drawn over several lines for people; records get them on one.
+fn error_message(err: &fourad::Error) -> String {
+    match err {
+        fourad::Error::Syntax(err) => {
+            let column = err.code[..err.span.start].chars().count() + 1;
+            let mut message = format!("{} at column {}", err.label(), column);
+            if let Some(suggestion) = &err.suggestion {
+                message.push_str(&format!(" ({})", suggestion));
+            }
+            message
+        }
+        err => err.to_string(),
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+// Writes records to stdout in one of the machine-readable formats.
+pub struct Writer {
+    format: Format,
+    records: Vec<Value>,
+    started: bool,
+}
+
+impl Writer {
+    pub fn new(format: Format) -> Writer {
+        Writer {
+            format,
+            records: vec![],
+            started: false,
+        }
+    }
+
+    pub fn write(&mut self, record: &Record) {
+        match self.format {
+            Format::Text => {}
+            Format::Json => self.records.push(record.to_json()),
+            Format::Jsonl => println!("{}", record.to_json()),
+            Format::Csv => {
+                if !self.started {
+                    println!("input,label,code,dice,dropped,total,error");
+                }
+                println!("{}", record.to_csv());
+            }
+        }
+        self.started = true;
+    }
+
+    pub fn finish(self) {
+        if self.format == Format::Json {
+            println!("{}", Value::Array(self.records));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fourad::RandRoller;
+
+    #[test]
+    fn test_record() {
+        let mut roller = RandRoller::from_seed(1);
+        let record = Record::roll("1d6+0", &RollOptions::default(), &mut roller);
+        assert_eq!(record.code.as_deref(), Some("d6"));
+        assert_eq!(record.dice.len(), 1);
+        assert_eq!(Some(record.dice[0][0]), record.total);
+        assert!(record.dropped[0].is_empty());
+
+        // The dice that count add up to the total, and the rest are listed apart.
+        let record = Record::roll("4d6kh3", &RollOptions::default(), &mut roller);
+        assert_eq!(record.dice[0].len(), 3);
+        assert_eq!(record.dropped[0].len(), 1);
+        assert_eq!(Some(record.dice[0].iter().sum()), record.total);
+        assert!(record.dropped[0][0] <= *record.dice[0].iter().min().unwrap());
+        assert_eq!(record.error, None);
+
+        let record = Record::roll("2d6+", &RollOptions::default(), &mut roller);
+        assert_eq!(record.code, None);
+        assert_eq!(record.total, None);
+        assert_eq!(
+            record.error.as_deref(),
+            Some("the code ends too soon at column 5 (did you mean 2d6?)")
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut roller = RandRoller::from_seed(1);
+        let options = RollOptions::default();
+        let records = Record::roll_all("2#d20, d8", Some("attack"), &options, &mut roller);
+        assert_eq!(records.len(), 3);
+        assert!(records
+            .iter()
+            .all(|record| record.label.as_deref() == Some("attack")));
+        assert_eq!(records[2].input, "d8");
+
+        let records = Record::roll_all("0#d6", Some("nothing"), &options, &mut roller);
+        assert_eq!(records[0].label.as_deref(), Some("nothing"));
+        assert!(records[0].error.is_some());
+    }
+
+    #[test]
+    fn test_formats() {
+        let record = Record {
+            input: "2d6 + 1".to_string(),
+            label: None,
+            code: Some("2d6+1".to_string()),
+            dice: vec![vec![3, 5]],
+            dropped: vec![vec![]],
+            total: Some(9),
+            error: None,
+        };
+        assert_eq!(
+            record.to_json().to_string(),
+            r#"{"code":"2d6+1","dice":[[3,5]],"dropped":[[]],"error":null,"input":"2d6 + 1","label":null,"total":9}"#
+        );
+        assert_eq!(record.to_csv(), "2d6 + 1,,2d6+1,3 5,,9,");
+
+        let record = Record {
+            input: "d6,\"".to_string(),
+            error: Some("bad".to_string()),
+            ..Record::default()
+        };
+        assert_eq!(record.to_csv(), "\"d6,\"\"\",,,,,,bad");
+        assert_eq!("jsonl".parse::<Format>(), Ok(Format::Jsonl));
+        assert!("xml".parse::<Format>().is_err());
+    }
+}
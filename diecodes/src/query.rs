@@ -0,0 +1,349 @@
+/*
+  A query asks how likely something is, rather than rolling it:
+
+    P(2d6+1 >= 8)          the total meets a target
+    P(d6+2 > d6+1)         one roll beats another
+    P(3 <= d6 <= 5)        the total is in a range, as is P(d6 in 3..5)
+    P(any 3d6>=5)          at least one die in a pool succeeds (also all and none)
+
+  The "P(...)" around a query is optional. A comparison straight after dice makes them a pool,
+  so comparing the total of some dice needs a space: "2d6 >= 8" rather than "2d6>=8".
+*/
+
+use crate::diagnostic::{locate, syntax_error};
+use crate::distribution::{Analyzer, Distribution};
+use crate::executor::RollOptions;
+use crate::grammar::{parse_compare, parse_expr, Compare, Dice, Directives, Expr};
+use crate::{Error, Result};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Query {
+    // lhs compare rhs
+    Compare(Expr, Compare, Expr),
+    // low compare value compare high
+    Between(Expr, Compare, Expr, Compare, Expr),
+    // How many dice in a pool succeed.
+    Pool(Quantifier, Dice),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Quantifier {
+    // At least one.
+    Any,
+    // As many as were rolled (not counting dice added by explosions).
+    All,
+    None,
+}
+
+impl Quantifier {
+    fn name(&self) -> &'static str {
+        match self {
+            Quantifier::Any => "any",
+            Quantifier::All => "all",
+            Quantifier::None => "none",
+        }
+    }
+}
+
+impl Query {
+    // The chance, from 0 to 1, that the query holds. Each Dice in the query is rolled
+    // separately.
+    pub fn probability(&self, options: &RollOptions) -> Result<f64> {
+        let directives = Directives::default();
+        let analyzer = Analyzer {
+            directives: &directives,
+            options,
+        };
+        let distribution = |expr: &Expr| analyzer.expr_distribution(expr);
+
+        match self {
+            Query::Compare(lhs, compare, rhs) => {
+                let (lhs, rhs) = (distribution(lhs)?, distribution(rhs)?);
+                Ok(lhs
+                    .iter()
+                    .map(|(lhs, p)| p * chance(&rhs, |rhs| compare.matches(lhs, rhs)))
+                    .sum())
+            }
+            Query::Between(low, low_compare, value, high_compare, high) => {
+                let low = distribution(low)?;
+                let value = distribution(value)?;
+                let high = distribution(high)?;
+                Ok(value
+                    .iter()
+                    .map(|(value, p)| {
+                        p * chance(&low, |low| low_compare.matches(low, value))
+                            * chance(&high, |high| high_compare.matches(value, high))
+                    })
+                    .sum())
+            }
+            Query::Pool(quantifier, dice) => {
+                let successes = analyzer.dice_distribution(dice)?;
+                // "all" means every die that is kept, not every die rolled.
+                let (kept, _) = dice.selection.kept(dice.repeat.number);
+                let number = kept as i64;
+                Ok(chance(&successes, |count| match quantifier {
+                    Quantifier::Any => count >= 1,
+                    Quantifier::All => count >= number,
+                    Quantifier::None => count == 0,
+                }))
+            }
+        }
+    }
+}
+
+fn chance(distribution: &Distribution, f: impl Fn(i64) -> bool) -> f64 {
+    distribution
+        .iter()
+        .filter(|(value, _)| f(*value))
+        .map(|(_, p)| p)
+        .sum()
+}
+
+impl Display for Query {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Query::Compare(lhs, compare, rhs) => write!(f, "P({} {} {})", lhs, compare, rhs),
+            Query::Between(low, low_compare, value, high_compare, high) => write!(
+                f,
+                "P({} {} {} {} {})",
+                low, low_compare, value, high_compare, high
+            ),
+            Query::Pool(quantifier, dice) => write!(f, "P({} {})", quantifier.name(), dice),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_query(s).map_err(|err| locate(err, s))
+    }
+}
+
+/*
+  GRAMMAR: query      --> 'P' '(' condition ')'
+  GRAMMAR:            --> condition
+*/
+fn parse_query(s: &str) -> Result<Query> {
+    let s = s.trim();
+    let (inner, wrapped) = match s.strip_prefix("P(").or_else(|| s.strip_prefix("p(")) {
+        Some(inner) => (inner, true),
+        None => (s, false),
+    };
+
+    let (query, rest) = parse_condition(inner)?;
+    let mut rest = rest.trim_start();
+    if wrapped {
+        rest = match rest.strip_prefix(')') {
+            Some(rest) => rest.trim_start(),
+            None if rest.is_empty() => {
+                return Err(syntax_error(
+                    Error::UnexpectedEndOfString(s.to_string()),
+                    rest,
+                    0,
+                ))
+            }
+            None => {
+                return Err(syntax_error(
+                    Error::UnexpectedChar(')', rest.to_string()),
+                    rest,
+                    rest.chars().next().map_or(0, char::len_utf8),
+                ))
+            }
+        };
+    }
+    if !rest.is_empty() {
+        return Err(syntax_error(
+            Error::UnexpectedEOL(rest.to_string()),
+            rest,
+            rest.len(),
+        ));
+    }
+    Ok(query)
+}
+
+/*
+  GRAMMAR: condition  --> quantifier expr
+  GRAMMAR:            --> expr "in" expr ".." expr
+  GRAMMAR:            --> expr compare expr compare expr
+  GRAMMAR:            --> expr compare expr
+  GRAMMAR: quantifier --> "any"
+  GRAMMAR:            --> "all"
+  GRAMMAR:            --> "none"
+*/
+fn parse_condition(s: &str) -> Result<(Query, &str)> {
+    let s = s.trim_start();
+    if let Some((quantifier, after)) = parse_quantifier(s) {
+        let after = after.trim_start();
+        let (expr, rest) = parse_expr(after)?;
+        return match expr {
+            Expr::Dice(dice) if dice.target.is_some() => Ok((Query::Pool(quantifier, dice), rest)),
+            _ => Err(syntax_error(
+                Error::InvalidQuery(format!(
+                    "'{}' needs a dice pool like 3d6>=5",
+                    quantifier.name()
+                )),
+                after,
+                after.len() - rest.len(),
+            )),
+        };
+    }
+
+    let (lhs, rest) = parse_expr(s)?;
+    let rest = rest.trim_start();
+    if let Some(after) = rest.strip_prefix("in ") {
+        let (low, rest) = parse_expr(after)?;
+        let rest = rest.trim_start();
+        let after = rest.strip_prefix("..").ok_or_else(|| {
+            syntax_error(
+                Error::UnexpectedChar('.', rest.to_string()),
+                rest,
+                rest.chars().next().map_or(0, char::len_utf8),
+            )
+        })?;
+        let (high, rest) = parse_expr(after)?;
+        let query = Query::Between(low, Compare::LessOrEqual, lhs, Compare::LessOrEqual, high);
+        return Ok((query, rest));
+    }
+
+    let (compare, after) = parse_comparison(rest).ok_or_else(|| match &lhs {
+        Expr::Dice(dice) if dice.target.is_some() => syntax_error(
+            Error::InvalidQuery(format!(
+                "{} is a pool; put a space before the comparison to compare the total",
+                dice
+            )),
+            s,
+            s.len() - rest.len(),
+        ),
+        _ => syntax_error(
+            Error::InvalidQuery("expected a comparison like '>= 8'".to_string()),
+            rest,
+            rest.len(),
+        ),
+    })?;
+    let (rhs, rest) = parse_expr(after)?;
+    if let Some((high_compare, after)) = parse_comparison(rest.trim_start()) {
+        let (high, rest) = parse_expr(after)?;
+        return Ok((Query::Between(lhs, compare, rhs, high_compare, high), rest));
+    }
+    Ok((Query::Compare(lhs, compare, rhs), rest))
+}
+
+fn parse_quantifier(s: &str) -> Option<(Quantifier, &str)> {
+    [Quantifier::Any, Quantifier::All, Quantifier::None]
+        .iter()
+        .find_map(|quantifier| {
+            s.strip_prefix(quantifier.name())
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .map(|rest| (*quantifier, rest))
+        })
+}
+
+// Unlike a reroll, a query has to say how to compare. "==" is the same as '='.
+fn parse_comparison(s: &str) -> Option<(Compare, &str)> {
+    if !s.starts_with(['<', '>', '=']) {
+        return None;
+    }
+    let (compare, rest) = parse_compare(s);
+    let rest = match compare {
+        Compare::Equal => rest.strip_prefix('=').unwrap_or(rest),
+        _ => rest,
+    };
+    Some((compare, rest))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn probability(query: &str) -> f64 {
+        query
+            .parse::<Query>()
+            .unwrap()
+            .probability(&RollOptions::default())
+            .unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let query: Query = "P(2d6+1 >= 8)".parse().unwrap();
+        assert_eq!(query.to_string(), "P(2d6+1 >= 8)");
+        let query: Query = "d6 in 2..4".parse().unwrap();
+        assert_eq!(query.to_string(), "P(2 <= d6 <= 4)");
+        let query: Query = "p( any 3d6>=5 )".parse().unwrap();
+        assert_eq!(query.to_string(), "P(any 3d6>=5)");
+        let query: Query = "2d6 == 7".parse().unwrap();
+        assert_eq!(query.to_string(), "P(2d6 = 7)");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let syntax = |query: &str| match query.parse::<Query>().unwrap_err() {
+            Error::Syntax(err) => *err,
+            err => panic!("{:?} is not a SyntaxError", err),
+        };
+        let err = syntax("P(2d6 >= 8");
+        assert_eq!(err.span, 10..10);
+        let err = syntax("P(2d6>=8)");
+        assert!(matches!(err.error, Error::InvalidQuery(_)));
+        assert_eq!(err.span, 2..8);
+        let err = syntax("P(2d6+1)");
+        assert_eq!(err.span, 7..8);
+        let err = syntax("any 2d6");
+        assert_eq!(err.span, 4..7);
+        let err = syntax("d6 > 3 and more");
+        assert!(matches!(err.error, Error::UnexpectedEOL(_)));
+    }
+
+    #[test]
+    fn test_probability() {
+        assert_close(probability("P(2d6+1 >= 8)"), 21.0 / 36.0);
+        assert_close(probability("P(d6 = 7)"), 0.0);
+        assert_close(probability("P(d6 < 3)"), 2.0 / 6.0);
+        // Ties lose.
+        assert_close(probability("P(d6 > d6)"), 15.0 / 36.0);
+        assert_close(probability("P(d6+2 > d6+1)"), 21.0 / 36.0);
+        assert_close(probability("P(3 <= d6 <= 5)"), 0.5);
+        assert_close(probability("P(2d6 in 2..3)"), 3.0 / 36.0);
+        assert_close(probability("P(d6 < 3 < d6)"), (2.0 / 6.0) * (3.0 / 6.0));
+    }
+
+    #[test]
+    fn test_pools() {
+        assert_close(probability("P(any 3d6>=5)"), 1.0 - (4.0f64 / 6.0).powi(3));
+        assert_close(probability("P(all 3d6>=5)"), (2.0f64 / 6.0).powi(3));
+        assert_close(probability("P(none 3d6>=5)"), (4.0f64 / 6.0).powi(3));
+        assert_close(probability("P(3d6>=5 >= 2)"), 7.0 / 27.0);
+        // Both of the highest two, and three of the four left after dropping the lowest.
+        assert_close(probability("P(all 4d6kh2>=5)"), 11.0 / 27.0);
+        assert_close(probability("P(all 4d6dl1>=5)"), 1.0 / 9.0);
+    }
+
+    #[test]
+    fn test_exploding() {
+        // Beating a level 4 minion with d6+2, with 6s exploding.
+        let query: Query = "P(d6E+2 > 4)".parse().unwrap();
+        assert_close(
+            query.probability(&RollOptions::default()).unwrap(),
+            4.0 / 6.0,
+        );
+        let options = RollOptions {
+            explode: true,
+            ..RollOptions::default()
+        };
+        let query: Query = "P(d6 >= 7)".parse().unwrap();
+        assert_close(query.probability(&options).unwrap(), 1.0 / 6.0);
+    }
+}
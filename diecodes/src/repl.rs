@@ -0,0 +1,165 @@
+use crate::output_codes;
+use fourad::{RollOptions, Roller};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::path::PathBuf;
+use tools::{error, quiet, spew};
+
+const PROMPT: &str = "fourad> ";
+
+const HELP: &str = "\
+Enter a dice code to roll it, or:
+  !!        roll the last code again
+  $         in a code, the total of the last roll (\"d6+$\")
+  help      show this help
+  quit      leave (as does Ctrl-D)
+
+Dice codes:
+  2d6+3, (d6+1)x2, 10-d6    add, subtract, multiply
+  d6/2, d6/^2, d6/~2        divide, rounding down, up, or to nearest
+  d66, d666, d10d10         digit dice
+  d6E, d8E7+, d6!!, d6!p    explode: compound, separate, penetrating
+  4d6kh3, 2d6kl1, 3d6dl1    keep or drop dice
+  d6r1, d6R<3               reroll once, reroll until
+  5d6>=5, 3d6>=4fs          pools: count the dice that hit the target
+  d6xd6 E, d6xd6 N          explode every die, or none";
+
+// What the session remembers between lines.
+#[derive(Debug, Default)]
+struct Session {
+    last_code: Option<String>,
+    last_total: Option<i64>,
+}
+
+impl Session {
+    // Replaces a line of "!!" with the last code, and "$" with the last total. Only a whole line
+    // repeats, since "d6!!" is a code of its own.
+    fn expand(&self, line: &str) -> Result<String, String> {
+        let mut code = line.trim().to_string();
+        if code == "!!" {
+            code = self
+                .last_code
+                .clone()
+                .ok_or_else(|| "there is no code to repeat yet".to_string())?;
+        }
+        if code.contains('$') {
+            let total = self
+                .last_total
+                .ok_or_else(|| "there is no previous result for '$' yet".to_string())?;
+            // Parenthesised so that a negative total still reads as one value.
+            let value = if total < 0 {
+                format!("({})", total)
+            } else {
+                total.to_string()
+            };
+            code = code.replace('$', &value);
+        }
+        Ok(code)
+    }
+
+    // After `code` rolls without an error, "!!" repeats it and "$" is its last total. A code
+    // that fails is forgotten, so "!!" still means the last one that worked.
+    fn remember(&mut self, code: String, totals: &[i64]) {
+        self.last_code = Some(code);
+        self.last_total = totals.last().copied().or(self.last_total);
+    }
+}
+
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".fourad_history"))
+}
+
+fn readline_error(err: ReadlineError) -> fourad::Error {
+    match err {
+        ReadlineError::Io(err) => fourad::Error::IO(err),
+        err => fourad::Error::GeneralError(err.to_string()),
+    }
+}
+
+// Reads codes from the terminal until the user quits. Errors in a code are reported and the
+// session carries on.
+pub fn run(options: &RollOptions, roller: &mut impl Roller) -> fourad::Result<()> {
+    let mut editor = DefaultEditor::new().map_err(readline_error)?;
+    let history = history_file();
+    if let Some(history) = &history {
+        // There is no history the first time.
+        let _ = editor.load_history(history);
+    }
+
+    let mut session = Session::default();
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            // Ctrl-C only abandons the line being typed; Ctrl-D leaves.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(readline_error(err)),
+        };
+        let line = line.trim();
+        match line {
+            "" => continue,
+            "help" | "?" => {
+                quiet!("{}", HELP);
+                continue;
+            }
+            "quit" | "exit" => break,
+            _ => {}
+        }
+
+        let code = match session.expand(line) {
+            Ok(code) => code,
+            Err(err) => {
+                error!("{}", err);
+                continue;
+            }
+        };
+        editor.add_history_entry(&code).map_err(readline_error)?;
+        if code != line {
+            spew!("{}", code);
+        }
+        match output_codes(&code, None, false, options, roller) {
+            Ok(totals) => session.remember(code, &totals),
+            Err(err) => error!("{}", err),
+        }
+    }
+
+    if let Some(history) = &history {
+        editor.save_history(history).map_err(readline_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let mut session = Session::default();
+        assert_eq!(session.expand(" 2d6 ").unwrap(), "2d6");
+        assert!(session.expand("!!").is_err());
+        assert!(session.expand("d6+$").is_err());
+
+        session.last_code = Some("2d6+1".to_string());
+        session.last_total = Some(8);
+        assert_eq!(session.expand("!!").unwrap(), "2d6+1");
+        assert_eq!(session.expand("d6+$").unwrap(), "d6+8");
+        assert_eq!(session.expand("d6!!+$").unwrap(), "d6!!+8");
+
+        session.last_total = Some(-3);
+        assert_eq!(session.expand("d6+$").unwrap(), "d6+(-3)");
+    }
+
+    #[test]
+    fn test_remember() {
+        let mut session = Session::default();
+        session.remember("3d6".to_string(), &[4, 11]);
+        assert_eq!(session.expand("!!").unwrap(), "3d6");
+        assert_eq!(session.expand("$").unwrap(), "11");
+
+        // Nothing was rolled, so the last total stands.
+        session.remember(",".to_string(), &[]);
+        assert_eq!(session.expand("!!").unwrap(), ",");
+        assert_eq!(session.expand("$").unwrap(), "11");
+    }
+}
@@ -1,15 +1,16 @@
 use crate::roller::Roller;
+use crate::Result;
 
 pub struct IterRoller<I>
 where
-    I: Iterator<Item = u8>,
+    I: Iterator<Item = u32>,
 {
     i: I,
 }
 
 impl<I> IterRoller<I>
 where
-    I: Iterator<Item = u8>,
+    I: Iterator<Item = u32>,
 {
     pub fn new(i: I) -> IterRoller<I> {
         IterRoller { i }
@@ -18,11 +19,11 @@ where
 
 impl<I> Roller for IterRoller<I>
 where
-    I: Iterator<Item = u8>,
+    I: Iterator<Item = u32>,
 {
-    fn roll(&mut self, _sides: u8) -> u8 {
+    fn try_roll(&mut self, _sides: u32) -> Result<u32> {
         // unwrap: this is for testing. The creator should ensure enough values for their use case.
-        self.i.next().unwrap()
+        Ok(self.i.next().unwrap())
     }
 }
 
@@ -33,7 +34,7 @@ mod test {
 
     #[test]
     fn test_vec() {
-        let vec = vec![2u8, 3, 5, 4];
+        let vec = vec![2u32, 3, 5, 4];
 
         let mut r = IterRoller::new(vec.into_iter());
         assert_eq!(r.roll(6), 2);
@@ -0,0 +1,85 @@
+use crate::roller::Roller;
+use crate::Result;
+use std::io::{BufRead, ErrorKind, Stderr, StdinLock, Write};
+
+// Asks a person for the value of each die they rolled by hand, asking again until the answer is
+// a number from 1 to the die's sides. The executor still does the rest: rerolls, explosions,
+// digit dice and arithmetic.
+pub struct ManualRoller<I: BufRead, O: Write> {
+    input: I,
+    output: O,
+}
+
+impl ManualRoller<StdinLock<'static>, Stderr> {
+    // Reads values from stdin, and prompts on stderr so that the results on stdout stay clean.
+    pub fn stdio() -> Self {
+        ManualRoller::new(std::io::stdin().lock(), std::io::stderr())
+    }
+}
+
+impl<I: BufRead, O: Write> ManualRoller<I, O> {
+    pub fn new(input: I, output: O) -> Self {
+        ManualRoller { input, output }
+    }
+}
+
+impl<I: BufRead, O: Write> Roller for ManualRoller<I, O> {
+    fn try_roll(&mut self, sides: u32) -> Result<u32> {
+        loop {
+            write!(self.output, "d{}: ", sides)?;
+            self.output.flush()?;
+
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Err(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("no value was given for a d{}", sides),
+                )
+                .into());
+            }
+            match line.trim().parse() {
+                Ok(die) if (1..=sides).contains(&die) => return Ok(die),
+                _ => writeln!(
+                    self.output,
+                    "'{}' is not a number from 1 to {}.",
+                    line.trim(),
+                    sides
+                )?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor::{execute_with_roller, RollOptions};
+    use crate::Error;
+
+    #[test]
+    fn test_prompts() {
+        let mut output = vec![];
+        let mut roller = ManualRoller::new(&b"x\n7\n 4 \n"[..], &mut output);
+        assert_eq!(roller.try_roll(6).unwrap(), 4);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "d6: 'x' is not a number from 1 to 6.\n\
+             d6: '7' is not a number from 1 to 6.\n\
+             d6: "
+        );
+    }
+
+    #[test]
+    fn test_execute() {
+        let mut roller = ManualRoller::new(&b"3\n5\n6\n2\n"[..], std::io::sink());
+        let options = RollOptions::default();
+        let result = execute_with_roller("d66".parse().unwrap(), &options, &mut roller).unwrap();
+        assert_eq!(result.total, 35);
+        let result = execute_with_roller("d6E+1".parse().unwrap(), &options, &mut roller).unwrap();
+        assert_eq!(result.total, 9);
+
+        // Running out of values is an error rather than a made up die.
+        let err = execute_with_roller("d6".parse().unwrap(), &options, &mut roller).unwrap_err();
+        assert!(matches!(err, Error::IO(_)));
+    }
+}
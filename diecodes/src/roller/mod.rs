@@ -1,26 +1,132 @@
-use rand::rngs::ThreadRng;
-use rand::{thread_rng, Rng};
+use crate::Result;
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, RngCore, SeedableRng};
 
 // A collection of non-random rollers to facilitate testing.
 #[cfg(test)]
 pub mod iterroller;
 
+mod manual;
+pub use manual::ManualRoller;
+
+mod record;
+pub use record::{RecordingRoller, ReplayRoller};
+
+// Where the dice get their values from. `try_roll` returns a value from 1 to `sides`, or an
+// error from a roller that can fail, like one that asks a person. The executor always rolls
+// through it.
 pub trait Roller {
-    fn roll(&mut self, sides: u8) -> u8;
+    fn try_roll(&mut self, sides: u32) -> Result<u32>;
+
+    // panic: if the roller fails. Only for rollers that can't, like RandRoller; anything else
+    // should call try_roll().
+    fn roll(&mut self, sides: u32) -> u32 {
+        self.try_roll(sides).expect("the roller failed")
+    }
+}
+
+// So that a roller can be lent out, or chosen at runtime as a `&mut dyn Roller`.
+impl<R: Roller + ?Sized> Roller for &mut R {
+    fn try_roll(&mut self, sides: u32) -> Result<u32> {
+        (**self).try_roll(sides)
+    }
+}
+
+impl<R: Roller + ?Sized> Roller for Box<R> {
+    fn try_roll(&mut self, sides: u32) -> Result<u32> {
+        (**self).try_roll(sides)
+    }
 }
 
+// A seeded random roller. Two RandRollers with the same seed will produce the same rolls.
 pub struct RandRoller {
-    rng: ThreadRng,
+    seed: u64,
+    rng: StdRng,
+}
+
+impl RandRoller {
+    pub fn from_seed(seed: u64) -> RandRoller {
+        RandRoller {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
 }
 
 impl Default for RandRoller {
+    // A roller with a randomly chosen seed.
     fn default() -> Self {
-        RandRoller { rng: thread_rng() }
+        RandRoller::from_seed(thread_rng().gen())
     }
 }
 
 impl Roller for RandRoller {
-    fn roll(&mut self, sides: u8) -> u8 {
-        self.rng.gen_range(1..=sides)
+    fn try_roll(&mut self, sides: u32) -> Result<u32> {
+        Ok(self.rng.gen_range(1..=sides))
+    }
+}
+
+// Rolls dice with any random number generator, such as a ChaCha, PCG or OsRng.
+pub struct RngRoller<R: RngCore> {
+    rng: R,
+}
+
+impl<R: RngCore> RngRoller<R> {
+    pub fn new(rng: R) -> RngRoller<R> {
+        RngRoller { rng }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.rng
+    }
+}
+
+impl<R: RngCore> Roller for RngRoller<R> {
+    fn try_roll(&mut self, sides: u32) -> Result<u32> {
+        Ok(self.rng.gen_range(1..=sides))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_same_seed() {
+        let mut first = RandRoller::from_seed(1234);
+        let mut second = RandRoller::from_seed(1234);
+        assert_eq!(first.seed(), 1234);
+
+        let first_rolls: Vec<u32> = (0..20).map(|_| first.roll(6)).collect();
+        let second_rolls: Vec<u32> = (0..20).map(|_| second.roll(6)).collect();
+        assert_eq!(first_rolls, second_rolls);
+        assert!(first_rolls.iter().all(|die| (1..=6).contains(die)));
+    }
+
+    #[test]
+    fn test_default_seed() {
+        let mut roller = RandRoller::default();
+        let mut replay = RandRoller::from_seed(roller.seed());
+
+        let rolls: Vec<u32> = (0..20).map(|_| roller.roll(20)).collect();
+        let replayed: Vec<u32> = (0..20).map(|_| replay.roll(20)).collect();
+        assert_eq!(rolls, replayed);
+    }
+
+    #[test]
+    fn test_rng_roller() {
+        let mut roller = RngRoller::new(StdRng::seed_from_u64(99));
+        let mut seeded = RandRoller::from_seed(99);
+        for _ in 0..20 {
+            assert_eq!(roller.roll(1_000_000), seeded.roll(1_000_000));
+        }
+
+        let mut roller = RngRoller::new(thread_rng());
+        let roller: &mut dyn Roller = &mut roller;
+        assert!((1..=u32::MAX).contains(&roller.roll(u32::MAX)));
     }
 }
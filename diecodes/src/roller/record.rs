@@ -0,0 +1,167 @@
+/*
+  A roll log has one line per die, giving its sides and the value it rolled:
+
+    d6: 4
+    d6: 6
+
+  so d66 logs two d6 lines, and an explosion logs a line for each extra die. Lines that don't
+  start with 'd' are ignored, so a log can carry notes.
+*/
+
+use crate::roller::Roller;
+use crate::{Error, Result};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+// Passes every roll through from another roller, writing it to a log.
+pub struct RecordingRoller<R: Roller, W: Write> {
+    roller: R,
+    log: W,
+}
+
+impl<R: Roller, W: Write> RecordingRoller<R, W> {
+    pub fn new(roller: R, log: W) -> Self {
+        RecordingRoller { roller, log }
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (self.roller, self.log)
+    }
+}
+
+impl<R: Roller, W: Write> Roller for RecordingRoller<R, W> {
+    // A roll that can't be logged can't be replayed, so failing to write it is an error.
+    fn try_roll(&mut self, sides: u32) -> Result<u32> {
+        let die = self.roller.try_roll(sides)?;
+        writeln!(self.log, "d{}: {}", sides, die)?;
+        // Flushed as it goes, so the log survives whatever happens next.
+        self.log.flush()?;
+        Ok(die)
+    }
+}
+
+// Plays back the rolls in a log, in order. Asking for a different die than the log has next
+// means the log doesn't match what is being rolled, and is an error.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReplayRoller {
+    // (sides, value) for each roll still to come.
+    rolls: VecDeque<(u32, u32)>,
+    played: usize,
+}
+
+impl ReplayRoller {
+    pub fn load(path: impl AsRef<Path>) -> Result<ReplayRoller> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    // How many rolls are left to play back.
+    pub fn remaining(&self) -> usize {
+        self.rolls.len()
+    }
+}
+
+impl Roller for ReplayRoller {
+    fn try_roll(&mut self, sides: u32) -> Result<u32> {
+        let roll = self.played + 1;
+        match self.rolls.pop_front() {
+            Some((logged, die)) if logged == sides => {
+                self.played += 1;
+                Ok(die)
+            }
+            Some((logged, _)) => Err(Error::InvalidReplay(format!(
+                "roll {} of the log is a d{}, but a d{} was rolled",
+                roll, logged, sides
+            ))),
+            None => Err(Error::InvalidReplay(format!(
+                "the log ran out after {} rolls",
+                self.played
+            ))),
+        }
+    }
+}
+
+/*
+  GRAMMAR: roll       --> 'd' number ':' number
+*/
+impl FromStr for ReplayRoller {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut rolls = VecDeque::new();
+        for (idx, line) in s.lines().enumerate() {
+            let line = line.trim();
+            let roll = match line.strip_prefix('d') {
+                Some(roll) => roll,
+                None => continue,
+            };
+            let invalid =
+                || Error::InvalidReplay(format!("line {} is not a roll: {}", idx + 1, line));
+            let (sides, die) = roll.split_once(':').ok_or_else(invalid)?;
+            let sides: u32 = sides.trim().parse().map_err(|_| invalid())?;
+            let die: u32 = die.trim().parse().map_err(|_| invalid())?;
+            if !(1..=sides).contains(&die) {
+                return Err(invalid());
+            }
+            rolls.push_back((sides, die));
+        }
+        Ok(ReplayRoller { rolls, played: 0 })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor::{execute_with_roller, RollOptions};
+    use crate::roller::RandRoller;
+
+    #[test]
+    fn test_record_and_replay() {
+        let options = RollOptions::default();
+        let codes = ["d66", "3d6!kh2", "d20+d8"];
+
+        let mut recorder = RecordingRoller::new(RandRoller::from_seed(7), vec![]);
+        let recorded: Vec<_> = codes
+            .iter()
+            .map(|code| execute_with_roller(code.parse().unwrap(), &options, &mut recorder))
+            .collect::<Result<_>>()
+            .unwrap();
+        let (_, log) = recorder.into_inner();
+        let log = String::from_utf8(log).unwrap();
+        assert!(log.starts_with("d6: "));
+
+        let mut replay: ReplayRoller = log.parse().unwrap();
+        let replayed: Vec<_> = codes
+            .iter()
+            .map(|code| execute_with_roller(code.parse().unwrap(), &options, &mut replay))
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(recorded, replayed);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[test]
+    fn test_replay_errors() {
+        let mut replay: ReplayRoller = "# notes\nd6: 3\n\nd8: 8\n".parse().unwrap();
+        assert_eq!(replay.remaining(), 2);
+        assert_eq!(replay.try_roll(6).unwrap(), 3);
+        assert_eq!(
+            replay.try_roll(6).unwrap_err().to_string(),
+            "Invalid replay: roll 2 of the log is a d8, but a d6 was rolled."
+        );
+        assert_eq!(
+            replay.try_roll(6).unwrap_err().to_string(),
+            "Invalid replay: the log ran out after 1 rolls."
+        );
+
+        // A log that runs out is an error for whoever rolls, not a panic.
+        let mut replay: ReplayRoller = "d6: 2".parse().unwrap();
+        let err = crate::roll("2d6", &RollOptions::default(), &mut replay).unwrap_err();
+        assert!(matches!(err, Error::InvalidReplay(_)));
+
+        assert!("d6: 7".parse::<ReplayRoller>().is_err());
+        assert!("d6 3".parse::<ReplayRoller>().is_err());
+        assert!("dx: 3".parse::<ReplayRoller>().is_err());
+    }
+}
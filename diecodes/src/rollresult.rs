@@ -0,0 +1,356 @@
+use crate::grammar::BinaryOp;
+use std::fmt::{Display, Formatter};
+
+// The full record of a single roll of a DieCode.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RollResult {
+    pub node: RollNode,
+    pub total: i64,
+}
+
+impl RollResult {
+    // The results for every Dice in the code, from left to right.
+    pub fn dice(&self) -> Vec<&DiceResult> {
+        self.node.dice()
+    }
+}
+
+impl Display for RollResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {}", self.node, self.total)
+    }
+}
+
+// The rolled form of a grammar::Expr.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RollNode {
+    Dice(DiceResult),
+    Constant(i64),
+    Negate(Box<RollNode>),
+    Binary(BinaryOp, Box<RollNode>, Box<RollNode>),
+}
+
+impl RollNode {
+    pub fn dice(&self) -> Vec<&DiceResult> {
+        match self {
+            RollNode::Dice(dice) => vec![dice],
+            RollNode::Constant(_) => vec![],
+            RollNode::Negate(node) => node.dice(),
+            RollNode::Binary(_, lhs, rhs) => {
+                let mut dice = lhs.dice();
+                dice.extend(rhs.dice());
+                dice
+            }
+        }
+    }
+
+    // Higher binds more tightly. Used to put back only the parentheses that are needed.
+    fn precedence(&self) -> u8 {
+        match self {
+            RollNode::Binary(op, _, _) => op.precedence(),
+            RollNode::Negate(_) => 3,
+            RollNode::Dice(_) | RollNode::Constant(_) => 4,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut Formatter<'_>, parens: bool) -> std::fmt::Result {
+        if parens {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl Display for RollNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RollNode::Dice(dice) => write!(f, "{}", dice),
+            RollNode::Constant(value) => write!(f, "{}", value),
+            RollNode::Negate(node) => {
+                write!(f, "-")?;
+                node.fmt_operand(f, node.precedence() < 3)
+            }
+            RollNode::Binary(op, lhs, rhs) => {
+                lhs.fmt_operand(f, lhs.precedence() < op.precedence())?;
+                write!(f, " {} ", op)?;
+                rhs.fmt_operand(f, rhs.precedence() <= op.precedence())
+            }
+        }
+    }
+}
+
+// The dice rolled for one grammar::Dice.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DiceResult {
+    pub dice: Vec<DieResult>,
+    pub subtotal: i64,
+}
+
+impl Display for DiceResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (idx, die) in self.dice.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", die)?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DieResult {
+    pub rolls: Rolls,
+    // The values that were rerolled away, in the order they were rolled.
+    pub rerolled: Vec<u32>,
+    // Set when this die exploded into a separate die.
+    pub exploded: bool,
+    // Set when a keep/drop rule leaves this die out of the subtotal.
+    pub dropped: bool,
+    // Set when the die hits the target of a dice pool.
+    pub succeeded: bool,
+}
+
+impl DieResult {
+    pub fn chain(rolls: Vec<u32>) -> DieResult {
+        DieResult {
+            rolls: Rolls::Chain(rolls),
+            rerolled: vec![],
+            exploded: false,
+            dropped: false,
+            succeeded: false,
+        }
+    }
+
+    pub fn penetrating(rolls: Vec<u32>) -> DieResult {
+        DieResult {
+            rolls: Rolls::Penetrating(rolls),
+            ..DieResult::chain(vec![])
+        }
+    }
+
+    pub fn digits(rolls: Vec<u32>) -> DieResult {
+        DieResult {
+            rolls: Rolls::Digits(rolls),
+            rerolled: vec![],
+            exploded: false,
+            dropped: false,
+            succeeded: false,
+        }
+    }
+
+    pub fn value(&self) -> i64 {
+        self.rolls.value()
+    }
+}
+
+impl Display for DieResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.dropped {
+            write!(f, "~")?;
+        }
+        for die in &self.rerolled {
+            write!(f, "{}↻", die)?;
+        }
+        write!(f, "{}", self.rolls)?;
+        if self.exploded {
+            write!(f, "!")?;
+        }
+        if self.succeeded {
+            write!(f, "*")?;
+        }
+        if self.dropped {
+            write!(f, "~")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Rolls {
+    // A single die, with every link of its explosion chain.
+    Chain(Vec<u32>),
+    // A penetrating explosion chain: every roll after the first counts one less.
+    Penetrating(Vec<u32>),
+    // A digit die (like d66 or d10d10), with one roll per digit.
+    Digits(Vec<u32>),
+}
+
+impl Rolls {
+    pub fn value(&self) -> i64 {
+        match self {
+            Rolls::Chain(rolls) => rolls.iter().map(|r| *r as i64).sum(),
+            Rolls::Penetrating(rolls) => {
+                rolls.iter().map(|r| *r as i64).sum::<i64>() - (rolls.len() as i64 - 1)
+            }
+            Rolls::Digits(rolls) => digits_value(rolls),
+        }
+    }
+}
+
+// A d10 reads its 10 as a 0 when it's rolled for a digit, and a result of all zeroes (like "00"
+// on a d10d10) is the highest result rather than the lowest.
+pub(crate) fn digits_value(rolls: &[u32]) -> i64 {
+    match rolls.iter().fold(0, |acc, r| acc * 10 + (*r % 10) as i64) {
+        0 => 10i64.pow(rolls.len() as u32),
+        value => value,
+    }
+}
+
+impl Display for Rolls {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (rolls, sep) = match self {
+            Rolls::Chain(rolls) => (rolls, "→"),
+            Rolls::Penetrating(rolls) => (rolls, "↘"),
+            Rolls::Digits(rolls) => (rolls, "|"),
+        };
+        for (idx, roll) in rolls.iter().enumerate() {
+            if idx > 0 {
+                write!(f, "{}", sep)?;
+            }
+            write!(f, "{}", roll)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_die_value() {
+        assert_eq!(DieResult::chain(vec![4]).value(), 4);
+        assert_eq!(DieResult::chain(vec![6, 6, 2]).value(), 14);
+        assert_eq!(DieResult::penetrating(vec![6, 6, 2]).value(), 12);
+        assert_eq!(DieResult::penetrating(vec![3]).value(), 3);
+        assert_eq!(DieResult::digits(vec![3, 5]).value(), 35);
+        assert_eq!(DieResult::digits(vec![10, 3]).value(), 3);
+        assert_eq!(DieResult::digits(vec![10, 10]).value(), 100);
+    }
+
+    fn dice(dice: Vec<DieResult>) -> RollNode {
+        let subtotal = dice
+            .iter()
+            .filter(|die| !die.dropped)
+            .map(DieResult::value)
+            .sum();
+        RollNode::Dice(DiceResult { dice, subtotal })
+    }
+
+    fn binary(op: BinaryOp, lhs: RollNode, rhs: RollNode) -> RollNode {
+        RollNode::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    #[test]
+    fn test_display() {
+        let result = RollResult {
+            node: binary(
+                BinaryOp::Add,
+                dice(vec![
+                    DieResult::chain(vec![4]),
+                    DieResult::chain(vec![6, 6, 2]),
+                ]),
+                RollNode::Constant(1),
+            ),
+            total: 19,
+        };
+        assert_eq!(result.to_string(), "[4, 6→6→2] + 1 = 19");
+
+        let result = RollResult {
+            node: binary(
+                BinaryOp::Multiply,
+                binary(
+                    BinaryOp::Subtract,
+                    dice(vec![DieResult::digits(vec![3, 4])]),
+                    RollNode::Constant(2),
+                ),
+                dice(vec![DieResult::chain(vec![2])]),
+            ),
+            total: 64,
+        };
+        assert_eq!(result.to_string(), "([3|4] - 2) x [2] = 64");
+
+        let result = RollResult {
+            node: dice(vec![
+                DieResult::chain(vec![4]),
+                DieResult {
+                    dropped: true,
+                    ..DieResult::chain(vec![1])
+                },
+                DieResult::chain(vec![3]),
+            ]),
+            total: 7,
+        };
+        assert_eq!(result.to_string(), "[4, ~1~, 3] = 7");
+
+        let result = RollResult {
+            node: dice(vec![DieResult {
+                rerolled: vec![1, 2],
+                ..DieResult::chain(vec![5])
+            }]),
+            total: 5,
+        };
+        assert_eq!(result.to_string(), "[1↻2↻5] = 5");
+
+        let result = RollResult {
+            node: dice(vec![
+                DieResult {
+                    exploded: true,
+                    ..DieResult::chain(vec![6])
+                },
+                DieResult::chain(vec![2]),
+                DieResult::penetrating(vec![6, 3]),
+            ]),
+            total: 16,
+        };
+        assert_eq!(result.to_string(), "[6!, 2, 6↘3] = 16");
+    }
+
+    #[test]
+    fn test_display_parens() {
+        let result = RollResult {
+            node: binary(
+                BinaryOp::Subtract,
+                RollNode::Constant(10),
+                binary(
+                    BinaryOp::Subtract,
+                    dice(vec![DieResult::chain(vec![3])]),
+                    RollNode::Constant(1),
+                ),
+            ),
+            total: 8,
+        };
+        assert_eq!(result.to_string(), "10 - ([3] - 1) = 8");
+
+        let result = RollResult {
+            node: RollNode::Negate(Box::new(binary(
+                BinaryOp::Add,
+                dice(vec![DieResult::chain(vec![3])]),
+                RollNode::Constant(1),
+            ))),
+            total: -4,
+        };
+        assert_eq!(result.to_string(), "-([3] + 1) = -4");
+    }
+
+    #[test]
+    fn test_dice() {
+        let result = RollResult {
+            node: binary(
+                BinaryOp::Add,
+                dice(vec![DieResult::chain(vec![4])]),
+                binary(
+                    BinaryOp::Multiply,
+                    RollNode::Constant(2),
+                    dice(vec![DieResult::chain(vec![5])]),
+                ),
+            ),
+            total: 14,
+        };
+        let subtotals: Vec<i64> = result.dice().iter().map(|d| d.subtotal).collect();
+        assert_eq!(subtotals, vec![4, 5]);
+    }
+}
@@ -0,0 +1,89 @@
+// With the "serde" feature, a DieCode is serialized as the string it displays as ("2d6+1"), and
+// deserialized by parsing that string. To store the parsed structure instead, mark the field with
+// `#[serde(with = "fourad::serialize::structured")]`.
+
+use crate::grammar::DieCode;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+impl Serialize for DieCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DieCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+pub mod structured {
+    use super::*;
+    use crate::grammar::{Directives, Expr};
+
+    #[derive(Serialize)]
+    struct Ref<'a> {
+        expr: &'a Expr,
+        directives: &'a Directives,
+    }
+
+    #[derive(Deserialize)]
+    struct Owned {
+        expr: Expr,
+        directives: Directives,
+    }
+
+    pub fn serialize<S: Serializer>(code: &DieCode, serializer: S) -> Result<S::Ok, S::Error> {
+        Ref {
+            expr: &code.expr,
+            directives: &code.directives,
+        }
+        .serialize(serializer)
+    }
+
+    // The structure is checked by parsing it back from text, so it can't hold a code that the
+    // parser would reject (like keeping 5 of 4 dice).
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DieCode, D::Error> {
+        let Owned { expr, directives } = Owned::deserialize(deserializer)?;
+        DieCode { expr, directives }
+            .to_string()
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        code: DieCode,
+        #[serde(with = "structured")]
+        ast: DieCode,
+    }
+
+    #[test]
+    fn test_string() {
+        let code: DieCode = "2d6 + 1".parse().unwrap();
+        assert_eq!(serde_json::to_string(&code).unwrap(), "\"2d6+1\"");
+        assert_eq!(serde_json::from_str::<DieCode>("\"2d6+1\"").unwrap(), code);
+        assert!(serde_json::from_str::<DieCode>("\"2d6+\"").is_err());
+    }
+
+    #[test]
+    fn test_structured() {
+        let config = Config {
+            code: "d66".parse().unwrap(),
+            ast: "4d6kh3 E".parse().unwrap(),
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains("\"KeepHighest\":3"));
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+
+        let bad = json.replace("\"KeepHighest\":3", "\"KeepHighest\":5");
+        assert!(serde_json::from_str::<Config>(&bad).is_err());
+    }
+}
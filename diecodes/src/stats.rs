@@ -0,0 +1,205 @@
+use crate::executor::{execute_with_roller, RollOptions};
+use crate::grammar::DieCode;
+use crate::roller::Roller;
+use crate::Result;
+use std::fmt::{Display, Formatter};
+
+// Histograms over a wider range of totals than this group them into ranges.
+const MAX_ROWS: usize = 30;
+const BAR_WIDTH: usize = 50;
+
+// Summary statistics of the totals of many rolls.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    // Sorted, so that the median and percentiles can be read off.
+    totals: Vec<i64>,
+    mean: f64,
+    variance: f64,
+}
+
+impl Stats {
+    // None if there are no totals to summarise.
+    pub fn new(mut totals: Vec<i64>) -> Option<Stats> {
+        if totals.is_empty() {
+            return None;
+        }
+        totals.sort_unstable();
+        let count = totals.len() as f64;
+        let mean = totals.iter().map(|total| *total as f64).sum::<f64>() / count;
+        let variance = totals
+            .iter()
+            .map(|total| (*total as f64 - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        Some(Stats {
+            totals,
+            mean,
+            variance,
+        })
+    }
+
+    // Rolls `code` `count` times.
+    pub fn sample(
+        code: &DieCode,
+        options: &RollOptions,
+        count: usize,
+        roller: &mut impl Roller,
+    ) -> Result<Option<Stats>> {
+        let totals = (0..count)
+            .map(|_| execute_with_roller(code.clone(), options, roller).map(|result| result.total))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Stats::new(totals))
+    }
+
+    pub fn count(&self) -> usize {
+        self.totals.len()
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn variance(&self) -> f64 {
+        self.variance
+    }
+
+    pub fn stddev(&self) -> f64 {
+        self.variance.sqrt()
+    }
+
+    pub fn min(&self) -> i64 {
+        self.totals[0]
+    }
+
+    pub fn max(&self) -> i64 {
+        self.totals[self.totals.len() - 1]
+    }
+
+    pub fn median(&self) -> f64 {
+        let mid = self.totals.len() / 2;
+        if self.totals.len().is_multiple_of(2) {
+            (self.totals[mid - 1] as f64 + self.totals[mid] as f64) / 2.0
+        } else {
+            self.totals[mid] as f64
+        }
+    }
+
+    // The smallest total that at least `percent`% of the rolls were no more than.
+    pub fn percentile(&self, percent: f64) -> i64 {
+        let rank = (percent / 100.0 * self.totals.len() as f64).ceil() as usize;
+        self.totals[rank.clamp(1, self.totals.len()) - 1]
+    }
+
+    // How many rolls fell in each range of totals from the lowest to the highest, as (low, high,
+    // count). Each range is one total unless there are too many to show.
+    pub fn histogram(&self) -> Vec<(i64, i64, usize)> {
+        let min = self.min() as i128;
+        let width = (self.max() as i128 - min) / MAX_ROWS as i128 + 1;
+        let mut counts = vec![0; ((self.max() as i128 - min) / width + 1) as usize];
+        for total in &self.totals {
+            counts[((*total as i128 - min) / width) as usize] += 1;
+        }
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(idx, count)| {
+                let low = min + idx as i128 * width;
+                let high = (low + width - 1).min(self.max() as i128);
+                (low as i64, high as i64, count)
+            })
+            .collect()
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "rolls:    {}", self.count())?;
+        writeln!(f, "mean:     {:.3}", self.mean())?;
+        writeln!(f, "median:   {}", self.median())?;
+        writeln!(f, "stddev:   {:.3}", self.stddev())?;
+        writeln!(f, "min:      {}", self.min())?;
+        writeln!(f, "max:      {}", self.max())?;
+        write!(f, "percentiles:")?;
+        for percent in &[5.0, 10.0, 25.0, 50.0, 75.0, 90.0, 95.0] {
+            write!(f, " {}%={}", percent, self.percentile(*percent))?;
+        }
+        writeln!(f)?;
+
+        let histogram = self.histogram();
+        let labels: Vec<String> = histogram
+            .iter()
+            .map(|(low, high, _)| {
+                if low == high {
+                    low.to_string()
+                } else {
+                    format!("{}-{}", low, high)
+                }
+            })
+            .collect();
+        let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+        let most = histogram.iter().map(|row| row.2).max().unwrap_or(1);
+        for ((_, _, count), label) in histogram.iter().zip(labels) {
+            write!(
+                f,
+                "\n{:>width$} | {:<bar$} {:5.2}%",
+                label,
+                "#".repeat(count * BAR_WIDTH / most),
+                *count as f64 * 100.0 / self.count() as f64,
+                width = label_width,
+                bar = BAR_WIDTH,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::roller::iterroller::IterRoller;
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::new(vec![4, 1, 3, 2]).unwrap();
+        assert_eq!(stats.count(), 4);
+        assert_eq!(stats.mean(), 2.5);
+        assert_eq!(stats.median(), 2.5);
+        assert_eq!(stats.variance(), 1.25);
+        assert_eq!((stats.min(), stats.max()), (1, 4));
+        assert_eq!(stats.percentile(25.0), 1);
+        assert_eq!(stats.percentile(50.0), 2);
+        assert_eq!(stats.percentile(100.0), 4);
+        assert_eq!(stats.percentile(0.0), 1);
+        assert_eq!(Stats::new(vec![]), None);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let stats = Stats::new(vec![2, 3, 3, 5]).unwrap();
+        assert_eq!(
+            stats.histogram(),
+            vec![(2, 2, 1), (3, 3, 2), (4, 4, 0), (5, 5, 1)]
+        );
+
+        // Too many totals for a row each.
+        let stats = Stats::new((0..100).collect()).unwrap();
+        let histogram = stats.histogram();
+        assert!(histogram.len() <= MAX_ROWS);
+        assert_eq!(histogram.last().unwrap().1, 99);
+        assert_eq!(histogram[0], (0, 3, 4));
+        assert_eq!(histogram.iter().map(|row| row.2).sum::<usize>(), 100);
+
+        let text = Stats::new(vec![1, 2, 2]).unwrap().to_string();
+        assert!(text.ends_with(&format!("2 | {} 66.67%", "#".repeat(BAR_WIDTH))));
+    }
+
+    #[test]
+    fn test_sample() {
+        let code = "2d6".parse().unwrap();
+        let mut roller = IterRoller::new(vec![1, 2, 6, 6, 3, 3].into_iter());
+        let stats = Stats::sample(&code, &RollOptions::default(), 3, &mut roller)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stats.totals, vec![3, 6, 12]);
+    }
+}
@@ -0,0 +1,476 @@
+use crate::distribution::{face_distribution, Analyzer, Distribution};
+use crate::executor::RollOptions;
+use crate::grammar::{
+    BinaryOp, Dice, DieCode, Explode, ExplodeStyle, Expr, Repeat, Selection, Sides,
+};
+use crate::{Error, Result};
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+
+// What can be said about the totals of a DieCode without rolling it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    // None when there is no limit, as with exploding dice.
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub mean: f64,
+    pub variance: f64,
+}
+
+impl Summary {
+    pub fn stddev(&self) -> f64 {
+        self.variance.sqrt()
+    }
+}
+
+// "avg 7.0, range 2–12", for annotating tables.
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "avg {:.1}, range ", self.mean)?;
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if min == max => write!(f, "{}", min),
+            (Some(min), Some(max)) => write!(f, "{}–{}", min, max),
+            (Some(min), None) => write!(f, "{} and up", min),
+            (None, Some(max)) => write!(f, "up to {}", max),
+            (None, None) => write!(f, "unlimited"),
+        }
+    }
+}
+
+impl DieCode {
+    pub fn summary(&self, options: &RollOptions) -> Result<Summary> {
+        let analyzer = Analyzer {
+            directives: &self.directives,
+            options,
+        };
+        let moments = expr_moments(&analyzer, &self.expr)?;
+        Ok(Summary {
+            min: moments.min.finite()?,
+            max: moments.max.finite()?,
+            mean: moments.mean,
+            variance: moments.variance(),
+        })
+    }
+
+    pub fn min(&self, options: &RollOptions) -> Result<Option<i64>> {
+        Ok(self.summary(options)?.min)
+    }
+
+    pub fn max(&self, options: &RollOptions) -> Result<Option<i64>> {
+        Ok(self.summary(options)?.max)
+    }
+
+    pub fn mean(&self, options: &RollOptions) -> Result<f64> {
+        Ok(self.summary(options)?.mean)
+    }
+
+    pub fn variance(&self, options: &RollOptions) -> Result<f64> {
+        Ok(self.summary(options)?.variance)
+    }
+}
+
+// One end of the range of a value. The variants are in order, so comparing bounds compares what
+// they stand for.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+enum Bound {
+    NegInf,
+    Finite(i128),
+    PosInf,
+}
+
+impl Bound {
+    fn finite(self) -> Result<Option<i64>> {
+        match self {
+            Bound::Finite(value) => i64::try_from(value).map(Some).map_err(|_| Error::Overflow),
+            _ => Ok(None),
+        }
+    }
+
+    fn signum(self) -> i128 {
+        match self {
+            Bound::NegInf => -1,
+            Bound::Finite(value) => value.signum(),
+            Bound::PosInf => 1,
+        }
+    }
+
+    fn neg(self) -> Bound {
+        match self {
+            Bound::NegInf => Bound::PosInf,
+            Bound::Finite(value) => Bound::Finite(-value),
+            Bound::PosInf => Bound::NegInf,
+        }
+    }
+
+    // Bounds are only ever added to bounds of the same end, so opposite infinities never meet.
+    fn add(self, other: Bound) -> Bound {
+        match (self, other) {
+            (Bound::Finite(lhs), Bound::Finite(rhs)) => Bound::Finite(lhs.saturating_add(rhs)),
+            (Bound::Finite(_), bound) | (bound, _) => bound,
+        }
+    }
+
+    // No value is actually infinite, so zero times an unbounded value is still zero.
+    fn mul(self, other: Bound) -> Bound {
+        match (self, other) {
+            (Bound::Finite(lhs), Bound::Finite(rhs)) => Bound::Finite(lhs.saturating_mul(rhs)),
+            _ => match self.signum() * other.signum() {
+                0 => Bound::Finite(0),
+                sign if sign < 0 => Bound::NegInf,
+                _ => Bound::PosInf,
+            },
+        }
+    }
+}
+
+// The range, mean and mean square of part of a code. Every Dice is rolled separately, so the
+// operands of each operator are independent and their moments combine directly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Moments {
+    min: Bound,
+    max: Bound,
+    mean: f64,
+    square: f64,
+}
+
+impl Moments {
+    fn constant(value: i128) -> Moments {
+        Moments {
+            min: Bound::Finite(value),
+            max: Bound::Finite(value),
+            mean: value as f64,
+            square: (value as f64).powi(2),
+        }
+    }
+
+    fn from_distribution(distribution: &Distribution) -> Moments {
+        let bound = |value: Option<i64>| Bound::Finite(value.unwrap_or(0) as i128);
+        Moments {
+            min: bound(distribution.min()),
+            max: bound(distribution.max()),
+            mean: distribution.mean(),
+            square: distribution
+                .iter()
+                .map(|(value, p)| (value as f64).powi(2) * p)
+                .sum(),
+        }
+    }
+
+    // The sum of `number` independent values with these moments.
+    fn repeated(&self, number: u32) -> Moments {
+        let times = Bound::Finite(number as i128);
+        let number = number as f64;
+        let mean = number * self.mean;
+        Moments {
+            min: self.min.mul(times),
+            max: self.max.mul(times),
+            mean,
+            square: number * self.variance() + mean * mean,
+        }
+    }
+
+    fn variance(&self) -> f64 {
+        (self.square - self.mean * self.mean).max(0.0)
+    }
+}
+
+fn expr_moments(analyzer: &Analyzer, expr: &Expr) -> Result<Moments> {
+    match expr {
+        Expr::Dice(dice) => dice_moments(analyzer, dice),
+        Expr::Constant(value) => Ok(Moments::constant(*value as i128)),
+        Expr::Negate(expr) => {
+            let moments = expr_moments(analyzer, expr)?;
+            Ok(Moments {
+                min: moments.max.neg(),
+                max: moments.min.neg(),
+                mean: -moments.mean,
+                square: moments.square,
+            })
+        }
+        Expr::Binary(op, lhs, rhs) => {
+            let lhs = expr_moments(analyzer, lhs)?;
+            let rhs = expr_moments(analyzer, rhs)?;
+            Ok(match op {
+                BinaryOp::Add => Moments {
+                    min: lhs.min.add(rhs.min),
+                    max: lhs.max.add(rhs.max),
+                    mean: lhs.mean + rhs.mean,
+                    square: lhs.square + 2.0 * lhs.mean * rhs.mean + rhs.square,
+                },
+                BinaryOp::Subtract => Moments {
+                    min: lhs.min.add(rhs.max.neg()),
+                    max: lhs.max.add(rhs.min.neg()),
+                    mean: lhs.mean - rhs.mean,
+                    square: lhs.square - 2.0 * lhs.mean * rhs.mean + rhs.square,
+                },
+                BinaryOp::Multiply => {
+                    let corners = [
+                        lhs.min.mul(rhs.min),
+                        lhs.min.mul(rhs.max),
+                        lhs.max.mul(rhs.min),
+                        lhs.max.mul(rhs.max),
+                    ];
+                    Moments {
+                        min: *corners.iter().min().unwrap(),
+                        max: *corners.iter().max().unwrap(),
+                        mean: lhs.mean * rhs.mean,
+                        square: lhs.square * rhs.square,
+                    }
+                }
+                BinaryOp::Divide(_) => {
+                    // Rounding has no closed form, so the quotient comes from its distribution.
+                    let mut moments =
+                        Moments::from_distribution(&analyzer.expr_distribution(expr)?);
+                    // Which is only cut short if the dividend has no limit.
+                    for dividend in [lhs.min, lhs.max] {
+                        for divisor in [rhs.min, rhs.max] {
+                            match dividend.mul(Bound::Finite(divisor.signum())) {
+                                Bound::NegInf => moments.min = Bound::NegInf,
+                                Bound::PosInf => moments.max = Bound::PosInf,
+                                Bound::Finite(_) => {}
+                            }
+                        }
+                    }
+                    moments
+                }
+            })
+        }
+    }
+}
+
+fn dice_moments(analyzer: &Analyzer, dice: &Dice) -> Result<Moments> {
+    let sides = analyzer.options.sides(dice)?;
+    // Digit dice never explode.
+    let explode = match sides {
+        Sides::Die(_) => analyzer
+            .options
+            .explosion(analyzer.directives, dice, &sides)?,
+        Sides::Digits(_) => None,
+    };
+    let unlimited = explode.is_some() && analyzer.options.max_explosions.is_none();
+
+    // Adding up every die has a closed form from the moments of one of them, whatever the number.
+    if dice.selection == Selection::All && dice.target.is_none() {
+        let die = match (&sides, explode) {
+            (Sides::Die(sides), Some(explode)) if unlimited => {
+                exploding_moments(&face_distribution(*sides, dice.reroll)?, explode, *sides)?
+            }
+            (Sides::Die(sides), None) if dice.reroll.is_none() => uniform_moments(*sides),
+            _ => {
+                let die = Dice {
+                    repeat: Repeat { number: 1 },
+                    ..dice.clone()
+                };
+                Moments::from_distribution(&analyzer.dice_distribution(&die)?)
+            }
+        };
+        return Ok(die.repeated(dice.repeat.number));
+    }
+
+    // Keeping, dropping and pools are exact from their distribution, apart from how long
+    // explosions go.
+    let mut moments = Moments::from_distribution(&analyzer.dice_distribution(dice)?);
+    // With E, !! or !p a kept die can grow without limit, though in a pool it still only counts
+    // once. With ! every die stays within its sides, but more and more of them can be kept, unless
+    // only a fixed number are.
+    let separate = explode.is_some_and(|explode| explode.style == ExplodeStyle::Separate);
+    let keeps_some = matches!(
+        dice.selection,
+        Selection::KeepHighest(_) | Selection::KeepLowest(_)
+    );
+    if unlimited && ((separate && !keeps_some) || (!separate && dice.target.is_none())) {
+        moments.max = Bound::PosInf;
+    }
+    Ok(moments)
+}
+
+// An ordinary die: 1 to `sides`, each as likely as the others.
+fn uniform_moments(sides: u32) -> Moments {
+    let sides = sides as f64;
+    Moments {
+        min: Bound::Finite(1),
+        max: Bound::Finite(sides as i128),
+        mean: (sides + 1.0) / 2.0,
+        square: (sides + 1.0) * (2.0 * sides + 1.0) / 6.0,
+    }
+}
+
+// A die that explodes without limit. Its total X is a roll R, plus another X if R explodes (less
+// 1 if penetrating), so E[X] and E[X²] can be solved for in closed form. Whether the extra rolls
+// are added in or kept as separate dice makes no difference to the sum.
+fn exploding_moments(face: &Distribution, explode: Explode, sides: u32) -> Result<Moments> {
+    let explodes = |value: i64| explode.explodes(value as u32, sides);
+    let p: f64 = face
+        .iter()
+        .filter(|(value, _)| explodes(*value))
+        .map(|(_, p)| p)
+        .sum();
+    let min = face
+        .iter()
+        .map(|(value, _)| value)
+        .find(|value| !explodes(*value))
+        .ok_or_else(|| Error::InvalidExplosion("every roll would explode".to_string()))?;
+
+    // The mean and mean square of R, and the mean of R over the rolls that explode.
+    let roll = face.mean();
+    let roll_square: f64 = face
+        .iter()
+        .map(|(value, p)| (value as f64).powi(2) * p)
+        .sum();
+    let exploding: f64 = face
+        .iter()
+        .filter(|(value, _)| explodes(*value))
+        .map(|(value, p)| value as f64 * p)
+        .sum();
+    let k = if explode.style == ExplodeStyle::Penetrate {
+        1.0
+    } else {
+        0.0
+    };
+
+    // X = R + [R explodes](X' - k)
+    let mean = (roll - p * k) / (1.0 - p);
+    let square =
+        (roll_square + 2.0 * exploding * (mean - k) + p * (k * k - 2.0 * k * mean)) / (1.0 - p);
+    Ok(Moments {
+        min: Bound::Finite(min as i128),
+        max: Bound::PosInf,
+        mean,
+        square,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::distribution::distribution;
+
+    fn summary(code: &str) -> Summary {
+        summary_with(code, &RollOptions::default())
+    }
+
+    fn summary_with(code: &str, options: &RollOptions) -> Summary {
+        code.parse::<DieCode>().unwrap().summary(options).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    // The closed forms should agree with the (cut short) distribution.
+    fn assert_matches_distribution(code: &str) {
+        let parsed: DieCode = code.parse().unwrap();
+        let expected = distribution(&parsed, &RollOptions::default()).unwrap();
+        let actual = parsed.summary(&RollOptions::default()).unwrap();
+        assert_close(actual.mean, expected.mean());
+        assert_close(actual.variance, expected.variance());
+        assert_eq!(actual.min, expected.min());
+    }
+
+    #[test]
+    fn test_bounded() {
+        let s = summary("2d6+1");
+        assert_eq!((s.min, s.max), (Some(3), Some(13)));
+        assert_close(s.mean, 8.0);
+        assert_close(s.variance, 35.0 / 6.0);
+        assert_eq!(s.to_string(), "avg 8.0, range 3–13");
+
+        let s = summary("d6xd6");
+        assert_eq!((s.min, s.max), (Some(1), Some(36)));
+        assert_close(s.mean, 12.25);
+        assert_close(s.variance, (91.0f64 / 6.0).powi(2) - 12.25 * 12.25);
+
+        let s = summary("10-2d6");
+        assert_eq!((s.min, s.max), (Some(-2), Some(8)));
+
+        let s = summary("d66");
+        assert_eq!((s.min, s.max), (Some(11), Some(66)));
+        assert_close(s.mean, 38.5);
+
+        let s = summary("4d6kh3");
+        assert_close(s.mean, 15869.0 / 1296.0);
+        assert_eq!(summary("5").to_string(), "avg 5.0, range 5");
+    }
+
+    #[test]
+    fn test_large() {
+        // Plain dice don't need a distribution, however many or large they are.
+        let s = summary("100d100");
+        assert_eq!((s.min, s.max), (Some(100), Some(10000)));
+        assert_close(s.mean, 5050.0);
+        assert_close(s.variance, 100.0 * (100.0 * 100.0 - 1.0) / 12.0);
+
+        let s = summary("d100000000");
+        assert_eq!((s.min, s.max), (Some(1), Some(100000000)));
+        assert_close(s.mean, 50000000.5);
+
+        let s = summary("50d6R1");
+        assert_eq!((s.min, s.max), (Some(100), Some(300)));
+        assert_close(s.mean, 200.0);
+    }
+
+    #[test]
+    fn test_exploding() {
+        let options = RollOptions {
+            explode: true,
+            ..RollOptions::default()
+        };
+        let s = summary_with("d6", &options);
+        assert_eq!((s.min, s.max), (Some(1), None));
+        assert_close(s.mean, 4.2);
+        assert_eq!(s.to_string(), "avg 4.2, range 1 and up");
+
+        let s = summary_with("-d6", &options);
+        assert_eq!((s.min, s.max), (None, Some(-1)));
+
+        for code in &[
+            "d6E", "2d8E7+", "d6!p", "3d6!", "d6r1E", "d6E-d4", "d6Exd6E",
+        ] {
+            assert_matches_distribution(code);
+        }
+
+        // Limited explosions are exact.
+        let limited = RollOptions {
+            explode: true,
+            max_explosions: Some(2),
+            ..RollOptions::default()
+        };
+        assert_eq!(summary_with("d6", &limited).max, Some(18));
+
+        let s = summary_with("d6/2", &options);
+        assert_eq!((s.min, s.max), (Some(0), None));
+    }
+
+    #[test]
+    fn test_pools_and_selection() {
+        // Each die counts once however often it explodes with E, but ! adds dice.
+        assert_eq!(summary("3d6E>=5").max, Some(3));
+        assert_eq!(summary("3d6!>=5").max, None);
+        assert_eq!(summary("3d6!kh2>=5").max, Some(2));
+
+        // Only three dice are kept however many ! adds, and none of them can be over 6.
+        assert_eq!(summary("4d6!kh3").max, Some(18));
+        assert_eq!(summary("4d6!kh3").min, Some(3));
+        // Every die's chain ends below 6, so the lowest three are at most 5 each.
+        assert_eq!(summary("10d6!kl3").max, Some(15));
+        // But dropping one leaves all the rest, and E lets a kept die grow.
+        assert_eq!(summary("4d6!dl1").max, None);
+        assert_eq!(summary("3d6Ekh2").max, None);
+    }
+
+    #[test]
+    fn test_errors() {
+        // Rerolling the only face that doesn't explode would never end.
+        assert!("d2R1E".parse::<DieCode>().is_err());
+        let code: DieCode = "d6/(d2-1)".parse().unwrap();
+        assert!(matches!(
+            code.summary(&RollOptions::default()),
+            Err(Error::DivideByZero)
+        ));
+    }
+}
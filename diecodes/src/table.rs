@@ -0,0 +1,493 @@
+/*
+  A table file holds any number of tables. Each one starts with a header naming the table and
+  the dice code to roll on it, followed by one entry per line for a result or range of results:
+
+    # Blank lines and lines starting with '#' are ignored.
+    table room d66
+    11-16: An empty room.
+    21: {d6} goblins, carrying [treasure].
+
+  An entry's text can roll inline dice codes in braces, which are replaced by their total, and
+  roll on other tables named in square brackets.
+*/
+
+use crate::distribution::distribution;
+use crate::executor::{execute_with_roller, RollOptions};
+use crate::grammar::DieCode;
+use crate::roller::Roller;
+use crate::{Error, Result};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+use tools::verbose;
+
+// How deeply tables may refer to each other, so a table that refers to itself can't go forever.
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Table {
+    pub name: String,
+    pub code: DieCode,
+    pub entries: Vec<Entry>,
+}
+
+impl Table {
+    pub fn entry(&self, result: i64) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.low <= result && result <= entry.high)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Entry {
+    pub low: i64,
+    pub high: i64,
+    pub text: Vec<Segment>,
+}
+
+impl Entry {
+    // "1-3", or "7+" if there is no top to it.
+    fn range(&self) -> String {
+        if self.high == i64::MAX {
+            format!("{}+", self.low)
+        } else {
+            format!("{}-{}", self.low, self.high)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Segment {
+    Text(String),
+    // An inline code like "{d6}", replaced by its total.
+    Code(DieCode),
+    // Another table like "[treasure]", replaced by a roll on it.
+    Table(String),
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Tables {
+    tables: HashMap<String, Table>,
+}
+
+impl Tables {
+    pub fn load(path: impl AsRef<Path>) -> Result<Tables> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Table> {
+        self.tables.get(name)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.tables.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    // Parsing checks the tables with the default options, so other options (like exploding
+    // every d6) mean checking the table again first, along with the tables it refers to.
+    pub fn roll(
+        &self,
+        name: &str,
+        options: &RollOptions,
+        roller: &mut impl Roller,
+    ) -> Result<String> {
+        if *options != RollOptions::default() {
+            self.check(&[name], options)?;
+        }
+        self.roll_table(name, options, roller, 0)
+    }
+
+    fn roll_table(
+        &self,
+        name: &str,
+        options: &RollOptions,
+        roller: &mut impl Roller,
+        depth: usize,
+    ) -> Result<String> {
+        if depth >= MAX_DEPTH {
+            return Err(Error::InvalidTable(format!(
+                "tables refer to each other more than {} deep",
+                MAX_DEPTH
+            )));
+        }
+        let table = self
+            .get(name)
+            .ok_or_else(|| Error::InvalidTable(format!("there is no table named '{}'", name)))?;
+
+        let result = execute_with_roller(table.code.clone(), options, roller)?;
+        verbose!("{}: {}", name, result);
+        let entry = table.entry(result.total).ok_or_else(|| {
+            Error::InvalidTable(format!("{} has no entry for {}", name, result.total))
+        })?;
+
+        let mut text = String::new();
+        for segment in &entry.text {
+            match segment {
+                Segment::Text(s) => text.push_str(s),
+                Segment::Code(code) => {
+                    let result = execute_with_roller(code.clone(), options, roller)?;
+                    verbose!("{}", result);
+                    text.push_str(&result.total.to_string());
+                }
+                Segment::Table(name) => {
+                    text.push_str(&self.roll_table(name, options, roller, depth + 1)?)
+                }
+            }
+        }
+        Ok(text)
+    }
+}
+
+impl FromStr for Tables {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut tables = Tables::default();
+        let mut current: Option<Table> = None;
+        for (idx, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let in_line = |err: Error| Error::TableLine(idx + 1, Box::new(err));
+
+            if let Some(header) = line.strip_prefix("table ") {
+                if let Some(table) = current.take() {
+                    tables.add(table)?;
+                }
+                current = Some(parse_header(header).map_err(in_line)?);
+            } else if let Some(table) = current.as_mut() {
+                let entry = parse_entry(line).map_err(in_line)?;
+                if let Some(other) = table
+                    .entries
+                    .iter()
+                    .find(|other| entry.low <= other.high && other.low <= entry.high)
+                {
+                    return Err(in_line(Error::InvalidTable(format!(
+                        "{} overlaps {}",
+                        entry.range(),
+                        other.range()
+                    ))));
+                }
+                table.entries.push(entry);
+            } else {
+                return Err(in_line(Error::InvalidTable(
+                    "entry before the first table".to_string(),
+                )));
+            }
+        }
+        if let Some(table) = current.take() {
+            tables.add(table)?;
+        }
+
+        tables.check(&tables.names(), &RollOptions::default())?;
+        Ok(tables)
+    }
+}
+
+impl Tables {
+    fn add(&mut self, table: Table) -> Result<()> {
+        if self.tables.contains_key(&table.name) {
+            return Err(Error::InvalidTable(format!(
+                "there are two tables named '{}'",
+                table.name
+            )));
+        }
+        self.tables.insert(table.name.clone(), table);
+        Ok(())
+    }
+
+    // Every table that is referred to must exist, and every result a table can roll with
+    // `options` must have an entry. This checks the tables named in `names`, and every table
+    // they refer to, each once and in order.
+    fn check<'a>(&'a self, names: &[&'a str], options: &RollOptions) -> Result<()> {
+        let mut pending: Vec<&str> = names.iter().rev().copied().collect();
+        let mut checked = HashSet::new();
+        while let Some(name) = pending.pop() {
+            if !checked.insert(name) {
+                continue;
+            }
+            let table = self.get(name).ok_or_else(|| {
+                Error::InvalidTable(format!("there is no table named '{}'", name))
+            })?;
+            let mut refers = vec![];
+            for entry in &table.entries {
+                for segment in &entry.text {
+                    if let Segment::Table(name) = segment {
+                        if self.get(name).is_none() {
+                            return Err(Error::InvalidTable(format!(
+                                "{} refers to '{}', which does not exist",
+                                table.name, name
+                            )));
+                        }
+                        refers.push(name.as_str());
+                    }
+                }
+            }
+            pending.extend(refers.into_iter().rev());
+
+            let results = distribution(&table.code, options)?;
+            let missing = results
+                .iter()
+                .map(|(result, _)| result)
+                .find(|result| table.entry(*result).is_none());
+            if let Some(missing) = missing {
+                return Err(Error::InvalidTable(format!(
+                    "{} has no entry for {}",
+                    table.name, missing
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+  GRAMMAR: header     --> name code
+  GRAMMAR: entry      --> range ':' text
+  GRAMMAR: range      --> number
+  GRAMMAR:            --> number '-' number
+  GRAMMAR:            --> number '+'
+  GRAMMAR: text       --> '{' code '}' text
+  GRAMMAR:            --> '[' name ']' text
+  GRAMMAR:            --> char text
+  GRAMMAR:            -->
+*/
+fn parse_header(s: &str) -> Result<Table> {
+    let s = s.trim_start();
+    let (name, code) = s.split_at(s.find(char::is_whitespace).unwrap_or(s.len()));
+    if name.is_empty() {
+        return Err(Error::InvalidTable("a table needs a name".to_string()));
+    }
+    Ok(Table {
+        name: name.to_string(),
+        code: code.trim().parse()?,
+        entries: vec![],
+    })
+}
+
+fn parse_entry(s: &str) -> Result<Entry> {
+    let (range, text) = match s.find(':') {
+        Some(idx) => (&s[..idx], &s[idx + 1..]),
+        None => return Err(Error::UnexpectedChar(':', s.to_string())),
+    };
+    // "7+" is open-ended, for the results of a code that explodes.
+    let (low, high) = match (range.trim_end().strip_suffix('+'), range.split_once('-')) {
+        (Some(low), _) => (parse_result(low)?, i64::MAX),
+        (None, Some((low, high))) => (parse_result(low)?, parse_result(high)?),
+        (None, None) => (parse_result(range)?, parse_result(range)?),
+    };
+    if low > high {
+        return Err(Error::InvalidTable(format!(
+            "{}-{} is an empty range",
+            low, high
+        )));
+    }
+    Ok(Entry {
+        low,
+        high,
+        text: parse_text(text.trim())?,
+    })
+}
+
+fn parse_result(s: &str) -> Result<i64> {
+    let s = s.trim();
+    s.parse()
+        .map_err(|err| Error::ParseNumberError(s.to_string(), err))
+}
+
+fn parse_text(s: &str) -> Result<Vec<Segment>> {
+    let mut segments = vec![];
+    let mut rest = s;
+    while let Some(idx) = rest.find(['{', '[']) {
+        if idx > 0 {
+            segments.push(Segment::Text(rest[..idx].to_string()));
+        }
+        let close = if rest[idx..].starts_with('{') {
+            '}'
+        } else {
+            ']'
+        };
+        let end = match rest[idx..].find(close) {
+            Some(end) => idx + end,
+            None => return Err(Error::UnexpectedEndOfString(rest[idx..].to_string())),
+        };
+        let inner = rest[idx + 1..end].trim();
+        segments.push(if close == '}' {
+            Segment::Code(inner.parse()?)
+        } else {
+            Segment::Table(inner.to_string())
+        });
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest.to_string()));
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::roller::iterroller::IterRoller;
+
+    const TABLES: &str = "
+        # Comments and blank lines are skipped.
+
+        table room d6
+        1-3: An empty room.
+        4: {d3+1} goblins
+        5-6: A chest holding [treasure].
+
+        table treasure 2d6
+        2-6: {d6} gold pieces
+        7-12: a magic sword
+    ";
+
+    #[test]
+    fn test_parse() {
+        let tables: Tables = TABLES.parse().unwrap();
+        assert_eq!(tables.names(), vec!["room", "treasure"]);
+
+        let room = tables.get("room").unwrap();
+        assert_eq!(room.entries.len(), 3);
+        assert_eq!(room.entries[0].low, 1);
+        assert_eq!(room.entries[0].high, 3);
+        assert_eq!(
+            room.entries[2].text,
+            vec![
+                Segment::Text("A chest holding ".to_string()),
+                Segment::Table("treasure".to_string()),
+                Segment::Text(".".to_string()),
+            ]
+        );
+        assert_eq!(room.entry(4).unwrap().low, 4);
+        assert_eq!(room.entry(7), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = "1: Orphan".parse::<Tables>().unwrap_err();
+        assert!(matches!(err, Error::TableLine(1, _)));
+
+        // Every result needs an entry.
+        let err = "table t d6\n1-5: Something".parse::<Tables>().unwrap_err();
+        assert_eq!(err.to_string(), "Invalid table: t has no entry for 6.");
+
+        let err = "table t d6\n1-4: A\n4-6: B".parse::<Tables>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Line 3 of the tables: Invalid table: 4-6 overlaps 1-4."
+        );
+
+        let err = "table t d3\n1-3: [nowhere]".parse::<Tables>().unwrap_err();
+        assert!(matches!(err, Error::InvalidTable(_)));
+
+        let err = "table t d3\n1-3: {d6".parse::<Tables>().unwrap_err();
+        assert!(matches!(err, Error::TableLine(2, _)));
+
+        let err = "table t d6\n1-5: A\n4+: B".parse::<Tables>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Line 3 of the tables: Invalid table: 4+ overlaps 1-5."
+        );
+
+        let err = "table t d3\ntable t d6".parse::<Tables>().unwrap_err();
+        assert!(matches!(err, Error::InvalidTable(_)));
+    }
+
+    #[test]
+    fn test_roll() {
+        let tables: Tables = TABLES.parse().unwrap();
+        let options = RollOptions::default();
+
+        let mut roller = IterRoller::new(vec![2].into_iter());
+        assert_eq!(
+            tables.roll("room", &options, &mut roller).unwrap(),
+            "An empty room."
+        );
+
+        let mut roller = IterRoller::new(vec![4, 3].into_iter());
+        assert_eq!(
+            tables.roll("room", &options, &mut roller).unwrap(),
+            "4 goblins"
+        );
+
+        let mut roller = IterRoller::new(vec![6, 1, 2, 5].into_iter());
+        assert_eq!(
+            tables.roll("room", &options, &mut roller).unwrap(),
+            "A chest holding 5 gold pieces."
+        );
+
+        let mut roller = IterRoller::new(vec![].into_iter());
+        assert!(tables.roll("nowhere", &options, &mut roller).is_err());
+
+        // Exploding every d6 can roll past the end of the room table.
+        let options = RollOptions {
+            explode: true,
+            max_explosions: Some(1),
+            ..RollOptions::default()
+        };
+        let mut roller = IterRoller::new(vec![6, 1].into_iter());
+        assert_eq!(
+            tables
+                .roll("room", &options, &mut roller)
+                .unwrap_err()
+                .to_string(),
+            "Invalid table: room has no entry for 7."
+        );
+    }
+
+    #[test]
+    fn test_roll_checks_only_what_it_rolls() {
+        let tables: Tables = "
+            table big d66
+            11-66: a hoard
+            table room d6
+            1-6: a room
+            table loot d6
+            1-5: copper
+            6+: [big] and more
+        "
+        .parse()
+        .unwrap();
+        let options = RollOptions {
+            explode: true,
+            ..RollOptions::default()
+        };
+
+        // Exploding the d6 of room doesn't matter when rolling on the others.
+        let mut roller = IterRoller::new(vec![3, 4].into_iter());
+        assert_eq!(
+            tables.roll("big", &options, &mut roller).unwrap(),
+            "a hoard"
+        );
+        let mut roller = IterRoller::new(vec![6, 6, 2, 1, 1].into_iter());
+        assert_eq!(
+            tables.roll("loot", &options, &mut roller).unwrap(),
+            "a hoard and more"
+        );
+
+        let mut roller = IterRoller::new(vec![].into_iter());
+        assert_eq!(
+            tables
+                .roll("room", &options, &mut roller)
+                .unwrap_err()
+                .to_string(),
+            "Invalid table: room has no entry for 7."
+        );
+    }
+
+    #[test]
+    fn test_self_reference() {
+        let tables: Tables = "table t d2\n1: [t]\n2: done".parse().unwrap();
+        let mut roller = IterRoller::new(std::iter::repeat(1));
+        let err = tables
+            .roll("t", &RollOptions::default(), &mut roller)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidTable(_)));
+    }
+}
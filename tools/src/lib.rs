@@ -1,11 +1,26 @@
+use std::cell::RefCell;
 use std::sync::Mutex;
 
+mod sink;
+pub use sink::{Buffer, Sink};
+
 static INSTANCE: once_cell::sync::Lazy<Mutex<Spew>> = once_cell::sync::Lazy::new(|| {
     Mutex::new(Spew {
         level: SpewLevel::STANDARD,
+        sinks: [Sink::Stderr, Sink::Stdout, Sink::Stdout, Sink::Stdout],
     })
 });
 
+thread_local! {
+    // Overrides the sinks in INSTANCE for one thread.
+    static THREAD_SINK: RefCell<Option<Sink>> = const { RefCell::new(None) };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => ($crate::error_fa(format_args!($($arg)+)));
+}
+
 #[macro_export]
 macro_rules! quiet {
     ($($arg:tt)+) => ($crate::quiet_fa(format_args!($($arg)+)));
@@ -32,6 +47,14 @@ fn spew_(s: impl AsRef<str>) {
         .spew_at_level(SpewLevel::STANDARD, s)
 }
 
+pub fn error_fa(fa: std::fmt::Arguments) {
+    error_(std::fmt::format(fa))
+}
+
+fn error_(s: impl AsRef<str>) {
+    INSTANCE.lock().unwrap().spew_at_level(SpewLevel::ERROR, s)
+}
+
 pub fn quiet_fa(fa: std::fmt::Arguments) {
     quiet_(std::fmt::format(fa))
 }
@@ -55,8 +78,32 @@ pub fn set_level(level: SpewLevel) {
     INSTANCE.lock().unwrap().set_level(level)
 }
 
+pub fn level() -> SpewLevel {
+    INSTANCE.lock().unwrap().level
+}
+
+// Sends everything spewed, at any level, to `sink`. Until set, error! goes to stderr and the
+// rest to stdout.
+pub fn set_sink(sink: Sink) {
+    INSTANCE.lock().unwrap().sinks = [sink.clone(), sink.clone(), sink.clone(), sink];
+}
+
+// Sends the lines from one macro (QUIET for quiet!, and so on) to `sink`, so that, say, verbose!
+// can go to stderr while the rest stays on stdout.
+pub fn set_level_sink(level: SpewLevel, sink: Sink) {
+    INSTANCE.lock().unwrap().sinks[level as usize] = sink;
+}
+
+// Sends everything spewed on the current thread to `sink` instead, until this is called again
+// with None. The level is still the global one.
+pub fn set_thread_sink(sink: Option<Sink>) {
+    THREAD_SINK.with(|thread_sink| *thread_sink.borrow_mut() = sink);
+}
+
+// ERROR is below every level that can be set from the command line, so errors are always shown.
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub enum SpewLevel {
+    ERROR,
     QUIET,
     STANDARD,
     VERBOSE,
@@ -64,6 +111,8 @@ pub enum SpewLevel {
 
 struct Spew {
     level: SpewLevel,
+    // Indexed by SpewLevel.
+    sinks: [Sink; 4],
 }
 
 impl Spew {
@@ -73,13 +122,53 @@ impl Spew {
 
     fn spew_at_level(&self, level: SpewLevel, s: impl AsRef<str>) {
         if level <= self.level {
-            println!("{}", s.as_ref());
+            THREAD_SINK.with(|thread_sink| match &*thread_sink.borrow() {
+                Some(sink) => sink.write_line(s.as_ref()),
+                None => self.sinks[level as usize].write_line(s.as_ref()),
+            });
         }
     }
 }
 
 mod lines;
-pub use lines::{process_bufread, process_stdin};
+pub use lines::{process_bufread, process_stdin, Line, Processed};
 
 mod play;
 pub use play::{bool_iter, bool_iter_val, bool_iter_with, if_some, if_some_with};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_thread_sink() {
+        let buffer = Buffer::new();
+        set_thread_sink(Some(Sink::Buffer(buffer.clone())));
+        quiet!("one");
+        spew!("two {}", 2);
+        error!("oops");
+        // Not at the default level.
+        verbose!("three");
+        set_thread_sink(None);
+        spew!("not captured");
+        assert_eq!(buffer.take(), "one\ntwo 2\noops\n");
+        assert_eq!(buffer.contents(), "");
+
+        // Another thread still spews to the global sinks.
+        let other = Buffer::new();
+        set_thread_sink(Some(Sink::Buffer(other.clone())));
+        std::thread::spawn(|| spew!("elsewhere")).join().unwrap();
+        set_thread_sink(None);
+        assert_eq!(other.contents(), "");
+    }
+
+    #[test]
+    fn test_writer_sink() {
+        let path = std::env::temp_dir().join(format!("tools-sink-{}.txt", std::process::id()));
+        set_thread_sink(Some(Sink::file(&path).unwrap()));
+        spew!("to a file");
+        set_thread_sink(None);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "to a file\n");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,20 +1,154 @@
+use std::fmt::{Display, Formatter};
 use std::io::{BufRead, BufReader};
 
-pub fn process_stdin<E>(
-    f: impl FnMut(&str) -> std::result::Result<(), E>,
-) -> std::result::Result<(), E>
-where
-    E: From<std::io::Error>,
-{
+// One line of input worth processing: not blank and not a '#' comment. "label: text" has its
+// label split off, and both are trimmed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Line<'a> {
+    // Counting from 1, including the skipped lines.
+    pub number: usize,
+    pub label: Option<&'a str>,
+    pub text: &'a str,
+}
+
+impl<'a> Line<'a> {
+    fn parse(number: usize, line: &'a str) -> Option<Line<'a>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (label, text) = match line.split_once(':') {
+            Some((label, text)) => (Some(label.trim_end()), text.trim_start()),
+            None => (None, line),
+        };
+        Some(Line {
+            number,
+            label,
+            text,
+        })
+    }
+}
+
+// Echoes the line back, label and all.
+impl Display for Line<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.label {
+            Some(label) => write!(f, "{}: {}", label, self.text),
+            None => write!(f, "{}", self.text),
+        }
+    }
+}
+
+// How processing went: how many lines were processed, and the numbers of those that failed.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Processed {
+    pub lines: usize,
+    pub failed: Vec<usize>,
+}
+
+impl Processed {
+    pub fn succeeded(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl Display for Processed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let failed: Vec<String> = self.failed.iter().map(usize::to_string).collect();
+        write!(
+            f,
+            "{} of {} lines failed: {}",
+            self.failed.len(),
+            self.lines,
+            failed.join(", ")
+        )
+    }
+}
+
+pub fn process_stdin<E: Display>(
+    f: impl FnMut(Line) -> std::result::Result<(), E>,
+) -> std::io::Result<Processed> {
     process_bufread(&mut BufReader::new(std::io::stdin()), f)
 }
 
-pub fn process_bufread<E>(
+// Calls `f` on each line worth processing. An error from `f` is reported through error! with its
+// line number, and processing carries on; only failing to read stops it.
+pub fn process_bufread<E: Display>(
     bufread: &mut impl BufRead,
-    mut f: impl FnMut(&str) -> std::result::Result<(), E>,
-) -> std::result::Result<(), E>
-where
-    E: From<std::io::Error>,
-{
-    bufread.lines().try_for_each(|wrapped| f(&wrapped?))
+    mut f: impl FnMut(Line) -> std::result::Result<(), E>,
+) -> std::io::Result<Processed> {
+    let mut processed = Processed::default();
+    for (idx, line) in bufread.lines().enumerate() {
+        let line = line?;
+        let line = match Line::parse(idx + 1, &line) {
+            Some(line) => line,
+            None => continue,
+        };
+        processed.lines += 1;
+        if let Err(err) = f(line) {
+            // A message over several lines (like one pointing at a column) starts on its own.
+            let message = err.to_string();
+            if message.contains('\n') {
+                error!("Line {}:\n{}", line.number, message);
+            } else {
+                error!("Line {}: {}", line.number, message);
+            }
+            processed.failed.push(line.number);
+        }
+    }
+    Ok(processed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{set_thread_sink, Buffer, Sink};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Line::parse(1, "  "), None);
+        assert_eq!(Line::parse(1, " # a comment"), None);
+        let line = Line::parse(3, " fireball : 8d6 ").unwrap();
+        assert_eq!(line.label, Some("fireball"));
+        assert_eq!(line.text, "8d6");
+        assert_eq!(line.to_string(), "fireball: 8d6");
+        assert_eq!(Line::parse(4, "2d6").unwrap().to_string(), "2d6");
+    }
+
+    #[test]
+    fn test_process() {
+        let input = "1\n\n# skip me\nlabel: 2\nthree\n4\nfive\n";
+        let mut seen = vec![];
+        let buffer = Buffer::new();
+        set_thread_sink(Some(Sink::Buffer(buffer.clone())));
+        let processed = process_bufread(&mut input.as_bytes(), |line| {
+            seen.push((line.number, line.label.map(str::to_string)));
+            line.text.parse::<u32>().map(|_| ())
+        })
+        .unwrap();
+        set_thread_sink(None);
+        assert_eq!(
+            buffer.take(),
+            "Line 5: invalid digit found in string\nLine 7: invalid digit found in string\n"
+        );
+        assert_eq!(
+            seen,
+            vec![
+                (1, None),
+                (4, Some("label".to_string())),
+                (5, None),
+                (6, None),
+                (7, None)
+            ]
+        );
+        assert_eq!(
+            processed,
+            Processed {
+                lines: 5,
+                failed: vec![5, 7],
+            }
+        );
+        assert!(!processed.succeeded());
+        assert_eq!(processed.to_string(), "2 of 5 lines failed: 5, 7");
+    }
 }
@@ -0,0 +1,62 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// Where spewed lines go.
+#[derive(Clone, Default)]
+pub enum Sink {
+    #[default]
+    Stdout,
+    Stderr,
+    Writer(Arc<Mutex<dyn Write + Send>>),
+    Buffer(Buffer),
+}
+
+impl Sink {
+    pub fn writer(writer: impl Write + Send + 'static) -> Sink {
+        Sink::Writer(Arc::new(Mutex::new(writer)))
+    }
+
+    // Creates the file, or truncates it if it is already there.
+    pub fn file(path: impl AsRef<Path>) -> std::io::Result<Sink> {
+        Ok(Sink::writer(File::create(path)?))
+    }
+
+    pub(crate) fn write_line(&self, s: &str) {
+        match self {
+            Sink::Stdout => println!("{}", s),
+            Sink::Stderr => eprintln!("{}", s),
+            // There's nowhere to report a failure to write a diagnostic, so it's dropped.
+            Sink::Writer(writer) => {
+                let mut writer = writer.lock().unwrap();
+                let _ = writeln!(writer, "{}", s).and_then(|_| writer.flush());
+            }
+            Sink::Buffer(buffer) => {
+                let mut contents = buffer.0.lock().unwrap();
+                contents.push_str(s);
+                contents.push('\n');
+            }
+        }
+    }
+}
+
+// Keeps lines in memory, mostly so that tests can check what was spewed. Clones share the same
+// lines.
+#[derive(Debug, Clone, Default)]
+pub struct Buffer(Arc<Mutex<String>>);
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer::default()
+    }
+
+    pub fn contents(&self) -> String {
+        self.0.lock().unwrap().clone()
+    }
+
+    // Returns the lines so far, and empties the buffer.
+    pub fn take(&self) -> String {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}